@@ -17,11 +17,29 @@ mod jsdb_impl;
 #[cfg(feature = "js_db")]
 pub use jsdb_impl::jsKVDB as DB;
 
+// An error returned by the underlying database, such as a failed disk read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbError(pub String);
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "database error: {}", self.0)
+    }
+}
+
+impl std::error::Error for DbError {}
+
 // Bare metal database assumes the most basic functionality for a key value database
 pub trait BareMetalKVDb {
     // Get the value stored at this key
     fn fetch(&self, key: &[u8]) -> Option<Vec<u8>>;
 
+    // Get the value stored at this key, returning an error if the read fails
+    // instead of panicking. Databases which cannot fail can rely on `fetch`
+    fn try_fetch(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DbError> {
+        Ok(self.fetch(key))
+    }
+
     // Create a database given the default path
     // This cannot be implemented here since Self is not sized.
     fn new() -> Self;
@@ -1,4 +1,4 @@
-use crate::{BareMetalDiskDb, BareMetalKVDb};
+use crate::{BareMetalDiskDb, BareMetalKVDb, DbError};
 pub use rocksdb::DB;
 
 impl BareMetalDiskDb for DB {
@@ -17,6 +17,9 @@ impl BareMetalKVDb for DB {
     fn fetch(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.get(key).unwrap()
     }
+    fn try_fetch(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DbError> {
+        self.get(key).map_err(|err| DbError(err.to_string()))
+    }
     // Create a database given the default path
     fn new() -> Self {
         Self::from_path(Self::DEFAULT_PATH)
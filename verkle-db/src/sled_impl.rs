@@ -1,4 +1,4 @@
-use crate::{BareMetalDiskDb, BareMetalKVDb};
+use crate::{BareMetalDiskDb, BareMetalKVDb, DbError};
 pub use sled::Db as DB;
 
 impl BareMetalDiskDb for sled::Db {
@@ -14,6 +14,11 @@ impl BareMetalKVDb for sled::Db {
     fn fetch(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.get(key).unwrap().map(|i_vec| i_vec.to_vec())
     }
+    fn try_fetch(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DbError> {
+        self.get(key)
+            .map(|value| value.map(|i_vec| i_vec.to_vec()))
+            .map_err(|err| DbError(err.to_string()))
+    }
     // Create a database given the default path
     fn new() -> Self {
         Self::from_path(Self::DEFAULT_PATH)
@@ -3,6 +3,10 @@ use criterion::criterion_main;
 mod benchmarks;
 criterion_main! {
     benchmarks::precompute_scalar_mul::benches,
+    benchmarks::insert_1k::benches,
+    benchmarks::update_hot_key::benches,
+    benchmarks::proof_with_cache::benches,
+    benchmarks::sparse_update::benches,
     // benchmarks::insert_10k::benches,
     // benchmarks::edit_10k::benches,
     // benchmarks::proof_10k::benches,
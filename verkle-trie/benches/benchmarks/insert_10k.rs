@@ -1,18 +1,13 @@
 use crate::benchmarks::util::{generate_set_of_keys, KEYS_10K, PRECOMPUTED_TABLE};
 use criterion::BenchmarkId;
 use criterion::{black_box, criterion_group, BatchSize, Criterion};
-use verkle_db::BareMetalDiskDb;
 use verkle_trie::database::memory_db::MemoryDb;
 use verkle_trie::trie::Trie;
 fn insert_10k_from_10mil_step(c: &mut Criterion) {
     let mut group = c.benchmark_group("insert 10k");
 
     for initial_keys in (0..=10_000_000).step_by(100_000) {
-        use tempfile::tempdir;
-        let temp_dir = tempdir().unwrap();
-
-        // let db = verkle_db::DefaultSledDb::from_path(&temp_dir);
-        let db = MemoryDb::from_path(&temp_dir);
+        let db = MemoryDb::new();
         let mut trie = Trie::new(db, &*PRECOMPUTED_TABLE);
         // Initial set of keys
         let keys = generate_set_of_keys(initial_keys);
@@ -29,7 +24,7 @@ fn insert_10k_from_10mil_step(c: &mut Criterion) {
                     |mut trie| {
                         // Insert different keys
                         for key in KEYS_10K.iter() {
-                            black_box(trie.insert(*key, *key))
+                            black_box(trie.insert(*key, *key));
                         }
                     },
                     BatchSize::SmallInput,
@@ -0,0 +1,24 @@
+use crate::benchmarks::util::{KEYS_10K, PRECOMPUTED_TABLE};
+use criterion::{black_box, criterion_group, BatchSize, Criterion};
+use verkle_trie::database::memory_db::MemoryDb;
+use verkle_trie::trie::Trie;
+
+// Every insert updates a leaf, so this measures the per-leaf overhead in `update_stem_table`
+fn insert_1k_fresh(c: &mut Criterion) {
+    c.bench_function("insert 1k into an empty trie", |b| {
+        b.iter_batched(
+            || Trie::new(MemoryDb::new(), &*PRECOMPUTED_TABLE),
+            |mut trie| {
+                for key in KEYS_10K.iter().take(1_000) {
+                    black_box(trie.insert(*key, *key));
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = insert_1k_fresh);
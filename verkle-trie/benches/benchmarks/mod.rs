@@ -1,3 +1,7 @@
 pub mod insert_10k;
+pub mod insert_1k;
 pub mod precompute_scalar_mul;
+pub mod proof_with_cache;
+pub mod sparse_update;
+pub mod update_hot_key;
 pub mod util;
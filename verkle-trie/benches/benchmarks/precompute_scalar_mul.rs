@@ -17,4 +17,19 @@ fn scalar_mul_bench(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, scalar_mul_bench);
+// Commits to 10k scalars at a single index, as the delta updates for a hot slot do.
+// The precomputed table for the index is reused, so each commitment is one fixed-base scalar mul
+fn single_index_commitments_bench(c: &mut Criterion) {
+    let scalars: Vec<_> = (1..=10_000u64).map(Fr::from).collect();
+    let table = &*PRECOMPUTED_TABLE;
+
+    c.bench_function("10k single index commitments", |b| {
+        b.iter(|| {
+            for scalar in &scalars {
+                black_box(table.scalar_mul(*scalar, 7));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, scalar_mul_bench, single_index_commitments_bench);
@@ -0,0 +1,44 @@
+use crate::benchmarks::util::{KEYS_10K, PRECOMPUTED_TABLE};
+use criterion::{black_box, criterion_group, BatchSize, Criterion};
+use verkle_trie::database::memory_db::MemoryDb;
+use verkle_trie::proof::BranchPolynomialCache;
+use verkle_trie::trie::Trie;
+
+// Proves overlapping windows of keys, so the branches near the root are opened by every proof
+fn proof_overlapping_keys(c: &mut Criterion) {
+    let mut trie = Trie::new(MemoryDb::new(), &*PRECOMPUTED_TABLE);
+    for key in KEYS_10K.iter().take(1_000) {
+        trie.insert(*key, *key);
+    }
+    let key_windows: Vec<Vec<[u8; 32]>> = (0..10)
+        .map(|i| KEYS_10K[i * 10..i * 10 + 100].to_vec())
+        .collect();
+
+    let mut group = c.benchmark_group("prove 10 overlapping sets of 100 keys");
+    group.bench_function("without cache", |b| {
+        b.iter(|| {
+            for keys in &key_windows {
+                black_box(trie.create_verkle_proof(keys.iter().copied()));
+            }
+        })
+    });
+    group.bench_function("with cache", |b| {
+        b.iter_batched(
+            BranchPolynomialCache::new,
+            |mut cache| {
+                for keys in &key_windows {
+                    black_box(
+                        trie.create_verkle_proof_with_cache(keys.iter().copied(), &mut cache),
+                    );
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = proof_overlapping_keys);
@@ -0,0 +1,56 @@
+use crate::benchmarks::util::{KEYS_10K, PRECOMPUTED_TABLE};
+use ark_ff::{One, Zero};
+use bandersnatch::Fr;
+use criterion::{black_box, criterion_group, BatchSize, Criterion};
+use verkle_trie::database::memory_db::MemoryDb;
+use verkle_trie::trie::Trie;
+use verkle_trie::Committer;
+
+// A zero delta returns the identity without a scalar multiplication
+fn zero_scalar_mul(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scalar_mul");
+    let committer = &*PRECOMPUTED_TABLE;
+
+    group.bench_function("zero", |b| {
+        b.iter(|| black_box(committer.scalar_mul(black_box(Fr::zero()), 5)))
+    });
+    group.bench_function("non-zero", |b| {
+        b.iter(|| black_box(committer.scalar_mul(black_box(-Fr::one()), 5)))
+    });
+    group.finish();
+}
+
+// Overwrites 1k values. A value is committed to as a low and a high 16 byte half,
+// so when only the high half changes, the delta for the low half is zero
+fn sparse_update_1k(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update 1k values");
+
+    let mut trie = Trie::new(MemoryDb::new(), &*PRECOMPUTED_TABLE);
+    for key in KEYS_10K.iter().take(1_000) {
+        trie.insert(*key, *key);
+    }
+
+    let updates = [("both halves", 0), ("high half", 16)];
+    for (name, changed_byte) in updates {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || trie.clone(),
+                |mut trie| {
+                    for key in KEYS_10K.iter().take(1_000) {
+                        let mut value = *key;
+                        value[changed_byte] = !value[changed_byte];
+                        value[31] = !value[31];
+                        black_box(trie.insert(*key, value));
+                    }
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = zero_scalar_mul, sparse_update_1k);
@@ -0,0 +1,48 @@
+use crate::benchmarks::util::{KEYS_10K, PRECOMPUTED_TABLE};
+use criterion::{black_box, criterion_group, BatchSize, Criterion};
+use verkle_trie::database::memory_db::MemoryDb;
+use verkle_trie::trie::Trie;
+
+// Updates the same key 10k times, like a counter, using `insert` and `update_hot_key`
+fn update_one_key_10k(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update one key 10k times");
+
+    let mut trie = Trie::new(MemoryDb::new(), &*PRECOMPUTED_TABLE);
+    for key in KEYS_10K.iter() {
+        trie.insert(*key, *key);
+    }
+    let hot_key = KEYS_10K[0];
+
+    group.bench_function("insert", |b| {
+        b.iter_batched(
+            || trie.clone(),
+            |mut trie| {
+                for counter in 0..10_000u32 {
+                    let mut value = [0u8; 32];
+                    value[0..4].copy_from_slice(&counter.to_le_bytes());
+                    black_box(trie.insert(hot_key, value));
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("update_hot_key", |b| {
+        b.iter_batched(
+            || trie.clone(),
+            |mut trie| {
+                for counter in 0..10_000u32 {
+                    let mut value = [0u8; 32];
+                    value[0..4].copy_from_slice(&counter.to_le_bytes());
+                    black_box(trie.update_hot_key(hot_key, value))
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = update_one_key_10k);
@@ -1,6 +1,10 @@
 // TODO this file has a lot of redundant code since we no longer use a variable width tree
 
 // Remove duplicate code below and move into trie module
+use crate::two_pow_128;
+use ark_ff::PrimeField;
+use bandersnatch::Fr;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Key(ByteArr);
 
@@ -36,6 +40,17 @@ impl Key {
         Key(ByteArr::max())
     }
 
+    // The first 31 bytes of a key are the stem, which identifies the stem node that the key is stored under
+    pub fn stem(&self) -> [u8; 31] {
+        let mut stem = [0u8; 31];
+        stem.copy_from_slice(&self.as_bytes()[0..31]);
+        stem
+    }
+    // The last byte of a key is the suffix, which is the index of the leaf in its stem node
+    pub fn suffix(&self) -> u8 {
+        self.as_bytes()[31]
+    }
+
     pub fn path_indices(&self) -> impl Iterator<Item = u8> + '_ {
         let bytes = self.as_bytes();
         bytes.to_vec().into_iter()
@@ -83,10 +98,34 @@ impl Value {
     pub const fn max() -> Value {
         Value(ByteArr::max())
     }
+    // Returns the low and high field elements that a stem commits to for this value.
+    // The low 16 bytes have 2^128 added to them, so that a stored zero value
+    // is distinguishable from an empty entry
+    pub fn to_field_pair(self) -> (Fr, Fr) {
+        let bytes = self.as_bytes();
+        let value_low = Fr::from_le_bytes_mod_order(&bytes[0..16]) + two_pow_128();
+        let value_high = Fr::from_le_bytes_mod_order(&bytes[16..32]);
+        (value_low, value_high)
+    }
 }
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 struct ByteArr(pub [u8; 32]);
 
+// Compares two byte slices in constant time with respect to their contents,
+// so that the time taken does not reveal where they first differ.
+// Slices of different lengths are never equal; their lengths are not treated as secret
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a
+        .iter()
+        .zip(b)
+        .fold(0u8, |diff, (byte_a, byte_b)| diff | (byte_a ^ byte_b));
+    // Stop the compiler from turning the fold into an early exit comparison
+    std::hint::black_box(diff) == 0
+}
+
 impl ByteArr {
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
@@ -124,3 +163,91 @@ fn basic() {
     ];
     dbg!(Key::path_difference(a, b));
 }
+
+#[test]
+fn stem_and_suffix() {
+    let mut arr = [0u8; 32];
+    for (i, byte) in arr.iter_mut().enumerate() {
+        *byte = i as u8 + 1;
+    }
+    let key = Key::from_arr(arr);
+
+    let expected_stem: Vec<u8> = (1..=31).collect();
+    assert_eq!(key.stem().to_vec(), expected_stem);
+    assert_eq!(key.suffix(), 32);
+}
+
+#[test]
+fn ct_eq_matches_eq() {
+    let mut other = [0u8; 32];
+    other[31] = 1;
+    let arrays = [[0u8; 32], [0xffu8; 32], other];
+
+    for a in arrays {
+        for b in arrays {
+            assert_eq!(ct_eq(&a, &b), a == b);
+        }
+    }
+    assert!(!ct_eq(&[0u8; 31], &[0u8; 32]));
+}
+
+#[test]
+fn value_to_field_pair() {
+    use ark_ff::Zero;
+
+    let mut mixed = [0u8; 32];
+    mixed[0] = 7;
+    mixed[16] = 9;
+    for value in [[0u8; 32], [1u8; 32], [255u8; 32], mixed] {
+        // This is the encoding from `update_stem_table`, for a leaf which was not present before
+        let delta_low = Fr::from_le_bytes_mod_order(&value[0..16]) + two_pow_128()
+            - Fr::from_le_bytes_mod_order(&[0u8; 16])
+            - Fr::zero();
+        let delta_high =
+            Fr::from_le_bytes_mod_order(&value[16..32]) - Fr::from_le_bytes_mod_order(&[0u8; 16]);
+
+        assert_eq!(
+            Value::from_arr(value).to_field_pair(),
+            (delta_low, delta_high)
+        );
+    }
+
+    // A zero value is not committed to as zero
+    let (zero_low, zero_high) = Value::zero().to_field_pair();
+    assert_eq!(zero_low, two_pow_128());
+    assert!(zero_high.is_zero());
+}
+
+#[test]
+fn path_difference_byte_aligned() {
+    let a = [0u8; 31];
+    let mut b = [0u8; 31];
+    b[3] = 1;
+
+    let (shared_path, p_diff_a, p_diff_b) = Key::path_difference(a, b);
+    assert_eq!(shared_path, vec![0, 0, 0]);
+    assert_eq!(p_diff_a, Some(0));
+    assert_eq!(p_diff_b, Some(1));
+}
+
+#[test]
+fn path_difference_sub_byte() {
+    // The trie has a width of 256, so each path index is a whole byte.
+    // Stems which agree on the top 7 bits of a byte, still diverge at that byte
+    // and the path indices returned are the full bytes.
+    let mut a = [7u8; 31];
+    let mut b = [7u8; 31];
+    a[5] = 0b1000_0000;
+    b[5] = 0b1000_0001;
+
+    let (shared_path, p_diff_a, p_diff_b) = Key::path_difference(a, b);
+    assert_eq!(shared_path, vec![7u8; 5]);
+    assert_eq!(p_diff_a, Some(0b1000_0000));
+    assert_eq!(p_diff_b, Some(0b1000_0001));
+
+    // If the stems are equal, all indices are shared and there is no difference
+    let (shared_path, p_diff_a, p_diff_b) = Key::path_difference(a, a);
+    assert_eq!(shared_path, a.to_vec());
+    assert_eq!(p_diff_a, None);
+    assert_eq!(p_diff_b, None);
+}
@@ -4,7 +4,8 @@ pub mod memory_db;
 pub mod meta;
 
 pub use default::VerkleDb;
-pub use meta::{BranchChild, BranchMeta, Meta, StemMeta};
+pub use meta::{BranchChild, BranchMeta, FlushChecksum, Meta, StemMeta};
+pub use verkle_db::DbError;
 pub trait ReadWriteHigherDb: ReadOnlyHigherDb + WriteOnlyHigherDb {}
 impl<T: ReadOnlyHigherDb + WriteOnlyHigherDb> ReadWriteHigherDb for T {}
 // There are two ways to use your database with this trie implementation:
@@ -44,6 +45,48 @@ pub trait WriteOnlyHigherDb {
     // TODO maybe we can return BranchChild, as the previous data could have been a stem or branch_meta
     // TODO then we can leave it upto the caller on how to deal with it
     fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, _depth: u8) -> Option<BranchMeta>;
+
+    // Stores the bytes of a value which is larger than a leaf, keyed by the hash that the leaf holds.
+    // Only databases which support values of any length need to implement this,
+    // by default an error is returned
+    fn insert_preimage(&mut self, hash: [u8; 32], _bytes: Vec<u8>) -> Result<(), DbError> {
+        Err(DbError(format!(
+            "this database cannot store preimages, tried to store the preimage of {}",
+            hex::encode(hash)
+        )))
+    }
+
+    // Stores the checksum of the trie which is being flushed, replacing the previous one.
+    // Databases which do not implement this ignore it, then the trie cannot verify a flush
+    fn insert_flush_checksum(&mut self, _checksum: FlushChecksum) {}
+
+    // The remove methods return the value which was removed, if there was one.
+    // They are needed to delete keys from the trie, which most databases do not support yet,
+    // so by default they return an error
+    fn remove_leaf(&mut self, key: [u8; 32]) -> Result<Option<[u8; 32]>, DbError> {
+        Err(DbError(format!(
+            "this database cannot remove leaves, tried to remove {}",
+            hex::encode(key)
+        )))
+    }
+
+    fn remove_stem(&mut self, key: [u8; 31]) -> Result<Option<StemMeta>, DbError> {
+        Err(DbError(format!(
+            "this database cannot remove stems, tried to remove {}",
+            hex::encode(key)
+        )))
+    }
+
+    // Removes the stem or branch which is the child at this path
+    fn remove_branch_child(
+        &mut self,
+        branch_child_id: &[u8],
+    ) -> Result<Option<BranchChild>, DbError> {
+        Err(DbError(format!(
+            "this database cannot remove branch children, tried to remove {}",
+            hex::encode(branch_child_id)
+        )))
+    }
 }
 
 // Notice that these take self, which effectively forces the implementer
@@ -61,6 +104,21 @@ pub trait ReadOnlyHigherDb {
     // TODO add a range query for the default database in verkle_db
     fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])>;
     fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]>;
+    // Same as `get_leaf`, however a failed read is returned as an error instead of panicking.
+    // Storage which cannot fail, such as memory databases, can rely on `get_leaf`
+    fn try_get_leaf(&self, key: [u8; 32]) -> Result<Option<[u8; 32]>, DbError> {
+        Ok(self.get_leaf(key))
+    }
+
+    // Returns the bytes stored with `insert_preimage` for this hash
+    fn get_preimage(&self, _hash: [u8; 32]) -> Option<Vec<u8>> {
+        None
+    }
+
+    // Returns the checksum stored with `insert_flush_checksum`
+    fn get_flush_checksum(&self) -> Option<FlushChecksum> {
+        None
+    }
 
     fn root_is_missing(&self) -> bool {
         let root = vec![];
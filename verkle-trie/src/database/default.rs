@@ -1,6 +1,6 @@
 use super::{
-    generic::GenericBatchDB, memory_db::MemoryDb, BranchChild, BranchMeta, Flush, ReadOnlyHigherDb,
-    StemMeta, WriteOnlyHigherDb,
+    generic::GenericBatchDB, memory_db::MemoryDb, BranchChild, BranchMeta, DbError, Flush,
+    FlushChecksum, ReadOnlyHigherDb, StemMeta, WriteOnlyHigherDb,
 };
 use crate::database::generic::GenericBatchWriter;
 use std::collections::HashMap;
@@ -10,8 +10,10 @@ use verkle_db::{BareMetalDiskDb, BareMetalKVDb, BatchDB, BatchWriter};
 // Then the methods needed for the Trie are auto implemented. In  particular, ReadOnlyHigherDb and WriteOnlyHigherDb
 // are implemented
 
-// All nodes at this level or above will be cached in memory
-const CACHE_DEPTH: u8 = 4;
+// By default, all nodes at this level or above will be cached in memory
+pub const DEFAULT_CACHE_DEPTH: u8 = 4;
+// By default, a flush writes at most this many items to the storage in a single batch
+pub const DEFAULT_FLUSH_BATCH_SIZE: usize = crate::FLUSH_BATCH as usize;
 
 // A wrapper database for those that just want to implement the permanent storage
 pub struct VerkleDb<Storage> {
@@ -21,9 +23,89 @@ pub struct VerkleDb<Storage> {
     // This stores the key-value pairs that we need to insert into the storage
     // This is flushed after every batch insert
     pub batch: MemoryDb,
-    // This stores the top 3 layers of the trie, since these are the most accessed
+    // This stores the top layers of the trie, since these are the most accessed
     // in the trie on average
     pub cache: MemoryDb,
+    // All nodes at this depth or above are stored in the cache
+    cache_depth: u8,
+    // The maximum number of items written to the storage in a single batch
+    flush_batch_size: usize,
+}
+
+impl<S> VerkleDb<S> {
+    pub fn cache_depth(&self) -> u8 {
+        self.cache_depth
+    }
+    // Sets the depth at or above which nodes are kept in the cache.
+    // This should be called before any nodes are inserted, since
+    // nodes which are already in the cache are not moved
+    pub fn set_cache_depth(&mut self, cache_depth: u8) {
+        self.cache_depth = cache_depth
+    }
+
+    pub fn flush_batch_size(&self) -> usize {
+        self.flush_batch_size
+    }
+    // Sets the maximum number of items written to the storage in a single batch.
+    // Flushing more items than this is split into multiple batches, so the flush
+    // is no longer atomic
+    pub fn set_flush_batch_size(&mut self, flush_batch_size: usize) {
+        assert!(flush_batch_size > 0, "the flush batch size cannot be zero");
+        self.flush_batch_size = flush_batch_size
+    }
+}
+
+impl<S: BareMetalKVDb> VerkleDb<S> {
+    // Reads the nodes at depth `levels` or above from the storage into the cache,
+    // along with the leaves of any stems at those depths.
+    //
+    // The cache is empty when an existing database is opened, so this should be called
+    // before the trie is used. Writes to nodes below the cache depth do not update the cache,
+    // so `levels` is limited to the cache depth
+    pub fn warm_cache(&mut self, levels: u8) {
+        let root = match self.storage.get_branch_meta(&[]) {
+            Some(root) => root,
+            None => return,
+        };
+        self.cache.insert_branch(vec![], root, 0);
+        self.warm_branch_children(vec![], levels.min(self.cache_depth));
+    }
+
+    fn warm_branch_children(&mut self, branch_id: Vec<u8>, levels: u8) {
+        let depth = branch_id.len() as u8 + 1;
+        if depth > levels {
+            return;
+        }
+
+        for (index, child) in self.storage.get_branch_children(&branch_id) {
+            let mut child_path = branch_id.clone();
+            child_path.push(index);
+
+            match child {
+                BranchChild::Branch(meta) => {
+                    self.cache.insert_branch(child_path.clone(), meta, depth);
+                    self.warm_branch_children(child_path, levels);
+                }
+                BranchChild::Stem(stem) => {
+                    // A stem without its meta data cannot be served from the cache,
+                    // so it is left to be read from the storage
+                    let stem_meta = match self.storage.get_stem_meta(stem) {
+                        Some(stem_meta) => stem_meta,
+                        None => continue,
+                    };
+                    self.cache.add_stem_as_branch_child(child_path, stem, depth);
+                    self.cache.insert_stem(stem, stem_meta, depth);
+
+                    for (suffix, value) in self.storage.get_stem_children(stem) {
+                        let mut key = [0u8; 32];
+                        key[0..31].copy_from_slice(&stem);
+                        key[31] = suffix;
+                        self.cache.insert_leaf(key, value, depth);
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl<S: BareMetalDiskDb> BareMetalDiskDb for VerkleDb<S> {
@@ -33,40 +115,93 @@ impl<S: BareMetalDiskDb> BareMetalDiskDb for VerkleDb<S> {
 
             batch: MemoryDb::new(),
             cache: MemoryDb::new(),
+            cache_depth: DEFAULT_CACHE_DEPTH,
+            flush_batch_size: DEFAULT_FLUSH_BATCH_SIZE,
         }
     }
 
     const DEFAULT_PATH: &'static str = S::DEFAULT_PATH;
 }
 
+// Writes items to the storage in batches of at most `batch_size` items
+struct ChunkedBatchWriter<'a, S: BatchDB> {
+    storage: &'a mut GenericBatchDB<S>,
+    writer: GenericBatchWriter<S::BatchWrite>,
+    num_items_in_writer: usize,
+    batch_size: usize,
+}
+
+impl<'a, S: BatchDB> ChunkedBatchWriter<'a, S> {
+    fn new(storage: &'a mut GenericBatchDB<S>, batch_size: usize) -> Self {
+        ChunkedBatchWriter {
+            storage,
+            writer: GenericBatchWriter {
+                inner: S::BatchWrite::new(),
+            },
+            num_items_in_writer: 0,
+            batch_size,
+        }
+    }
+
+    // This should be called after each item is written to the writer
+    fn item_written(&mut self) {
+        self.num_items_in_writer += 1;
+        if self.num_items_in_writer == self.batch_size {
+            self.flush_writer();
+        }
+    }
+
+    fn flush_writer(&mut self) {
+        let full_writer = std::mem::replace(&mut self.writer.inner, S::BatchWrite::new());
+        self.storage.flush(full_writer);
+        self.num_items_in_writer = 0;
+    }
+
+    // Flushes the items which did not fill a whole batch
+    fn finish(mut self) {
+        if self.num_items_in_writer > 0 {
+            self.flush_writer();
+        }
+    }
+}
+
 impl<S: BatchDB> Flush for VerkleDb<S> {
     // flush the batch to the storage
     fn flush(&mut self) {
-        let writer = S::BatchWrite::new();
-        let mut w = GenericBatchWriter { inner: writer };
+        let mut w = ChunkedBatchWriter::new(&mut self.storage, self.flush_batch_size);
 
         let now = std::time::Instant::now();
 
         for (key, value) in self.batch.leaf_table.iter() {
-            w.insert_leaf(*key, *value, 0);
+            w.writer.insert_leaf(*key, *value, 0);
+            w.item_written();
         }
 
         for (key, meta) in self.batch.stem_table.iter() {
-            w.insert_stem(*key, *meta, 0);
+            w.writer.insert_stem(*key, *meta, 0);
+            w.item_written();
         }
 
         for (branch_id, b_child) in self.batch.branch_table.iter() {
             let branch_id = branch_id.clone();
             match b_child {
                 BranchChild::Stem(stem_id) => {
-                    w.add_stem_as_branch_child(branch_id, *stem_id, 0);
+                    w.writer.add_stem_as_branch_child(branch_id, *stem_id, 0);
                 }
                 BranchChild::Branch(b_meta) => {
-                    w.insert_branch(branch_id, *b_meta, 0);
+                    w.writer.insert_branch(branch_id, *b_meta, 0);
                 }
             };
+            w.item_written();
+        }
+
+        if let Some(checksum) = self.batch.flush_checksum {
+            w.writer.insert_flush_checksum(checksum);
+            w.item_written();
         }
 
+        w.finish();
+
         let num_items = self.batch.num_items();
         println!(
             "write to batch time: {}, item count : {}",
@@ -74,8 +209,6 @@ impl<S: BatchDB> Flush for VerkleDb<S> {
             num_items
         );
 
-        self.storage.flush(w.inner);
-
         self.batch.clear();
     }
 }
@@ -94,6 +227,17 @@ impl<S: BareMetalKVDb> ReadOnlyHigherDb for VerkleDb<S> {
         self.storage.get_leaf(key)
     }
 
+    fn try_get_leaf(&self, key: [u8; 32]) -> Result<Option<[u8; 32]>, DbError> {
+        // The cache and batch are in memory, so only the disk read can fail
+        if let Some(val) = self.cache.get_leaf(key) {
+            return Ok(Some(val));
+        }
+        if let Some(val) = self.batch.get_leaf(key) {
+            return Ok(Some(val));
+        }
+        self.storage.try_get_leaf(key)
+    }
+
     fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
         // First try to get it from cache
         if let Some(val) = self.cache.get_stem_meta(stem_key) {
@@ -134,9 +278,9 @@ impl<S: BareMetalKVDb> ReadOnlyHigherDb for VerkleDb<S> {
     }
 
     fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
-        // Check the depth. If the branch is at CACHE_DEPTH or lower, then it will be in the cache
+        // Check the depth. If the children are at the cache depth or above, then they will be in the cache
         // TODO this assumes that the cache is populated on startup from disk
-        if branch_id.len() as u8 <= CACHE_DEPTH {
+        if (branch_id.len() as u8) < self.cache_depth {
             return self.cache.get_branch_children(branch_id);
         }
         // First get the children from storage
@@ -192,19 +336,36 @@ impl<S: BareMetalKVDb> ReadOnlyHigherDb for VerkleDb<S> {
             .map(|(index, val)| (index, val))
             .collect()
     }
+
+    fn get_flush_checksum(&self) -> Option<FlushChecksum> {
+        self.batch
+            .get_flush_checksum()
+            .or_else(|| self.storage.get_flush_checksum())
+    }
 }
 
-// Always save in the permanent storage and only save in the memorydb if the depth is <= cache depth
-impl<S> WriteOnlyHigherDb for VerkleDb<S> {
+// Always save in the permanent storage and only save in the memorydb if the depth is <= cache depth.
+//
+// The depth of a branch node or branch child is the length of its path, so that a node is either always
+// or never in the cache. A stem which was cached can be moved below the cache depth, when another stem
+// with the same prefix is inserted, so a cached stem and its leaves are still updated after it moves.
+// Otherwise reads, which try the cache first, would return stale values
+impl<S: BareMetalKVDb> WriteOnlyHigherDb for VerkleDb<S> {
     fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], depth: u8) -> Option<Vec<u8>> {
-        if depth <= CACHE_DEPTH {
+        let mut stem = [0u8; 31];
+        stem.copy_from_slice(&key[0..31]);
+        if depth <= self.cache_depth || self.cache.get_stem_meta(stem).is_some() {
             self.cache.insert_leaf(key, value, depth);
         }
-        self.batch.insert_leaf(key, value, depth)
+        // The batch is cleared after each flush, so a leaf which is not in it
+        // could still have a value in the storage
+        self.batch
+            .insert_leaf(key, value, depth)
+            .or_else(|| self.storage.get_leaf(key).map(|val| val.to_vec()))
     }
 
     fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, depth: u8) -> Option<StemMeta> {
-        if depth <= CACHE_DEPTH {
+        if depth <= self.cache_depth || self.cache.get_stem_meta(key).is_some() {
             self.cache.insert_stem(key, meta, depth);
         }
         self.batch.insert_stem(key, meta, depth)
@@ -216,7 +377,7 @@ impl<S> WriteOnlyHigherDb for VerkleDb<S> {
         stem_id: [u8; 31],
         depth: u8,
     ) -> Option<BranchChild> {
-        if depth <= CACHE_DEPTH {
+        if branch_child_id.len() as u8 <= self.cache_depth {
             self.cache
                 .add_stem_as_branch_child(branch_child_id.clone(), stem_id, depth);
         }
@@ -225,9 +386,369 @@ impl<S> WriteOnlyHigherDb for VerkleDb<S> {
     }
 
     fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, depth: u8) -> Option<BranchMeta> {
-        if depth <= CACHE_DEPTH {
+        if key.len() as u8 <= self.cache_depth {
             self.cache.insert_branch(key.clone(), meta, depth);
         }
         self.batch.insert_branch(key, meta, depth)
     }
+
+    fn insert_flush_checksum(&mut self, checksum: FlushChecksum) {
+        self.batch.insert_flush_checksum(checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VerkleDb, DEFAULT_CACHE_DEPTH, DEFAULT_FLUSH_BATCH_SIZE};
+    use crate::database::ReadOnlyHigherDb;
+    use crate::{trie::Trie, BasicCommitter};
+    use std::cell::{Cell, RefCell};
+    use std::collections::{HashMap, HashSet};
+    use verkle_db::{BareMetalDiskDb, BareMetalKVDb, BatchDB, BatchWriter, DbError};
+
+    // An in-memory key value store which counts the number of reads
+    #[derive(Default)]
+    pub(crate) struct CountingKVDb {
+        pub(crate) map: HashMap<Vec<u8>, Vec<u8>>,
+        pub(crate) num_fetches: Cell<usize>,
+        // The number of items in each batch that was flushed
+        pub(crate) batch_sizes: Vec<usize>,
+        // Fallible reads of these keys return an error
+        pub(crate) failing_keys: RefCell<HashSet<Vec<u8>>>,
+    }
+
+    impl BareMetalKVDb for CountingKVDb {
+        fn fetch(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.num_fetches.set(self.num_fetches.get() + 1);
+            self.map.get(key).cloned()
+        }
+        fn try_fetch(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DbError> {
+            if self.failing_keys.borrow().contains(key) {
+                return Err(DbError("injected read failure".to_string()));
+            }
+            Ok(self.fetch(key))
+        }
+        fn new() -> Self {
+            CountingKVDb::default()
+        }
+    }
+
+    impl BareMetalDiskDb for CountingKVDb {
+        fn from_path<P: AsRef<std::path::Path>>(_path: P) -> Self {
+            CountingKVDb::default()
+        }
+
+        const DEFAULT_PATH: &'static str = "";
+    }
+
+    pub(crate) struct VecBatch(pub(crate) Vec<(Vec<u8>, Vec<u8>)>);
+
+    impl BatchWriter for VecBatch {
+        fn new() -> Self {
+            VecBatch(Vec::new())
+        }
+
+        fn batch_put(&mut self, key: &[u8], val: &[u8]) {
+            self.0.push((key.to_vec(), val.to_vec()))
+        }
+    }
+
+    impl BatchDB for CountingKVDb {
+        type BatchWrite = VecBatch;
+
+        fn flush(&mut self, batch: Self::BatchWrite) {
+            self.batch_sizes.push(batch.0.len());
+            self.map.extend(batch.0)
+        }
+    }
+
+    #[test]
+    fn cache_depth_serves_top_levels() {
+        let db = VerkleDb::<CountingKVDb>::from_path("");
+        let mut trie = Trie::new_with_cache_depth(db, BasicCommitter, 2);
+
+        // These keys share their first three bytes, so there will be
+        // branch nodes at [0], [0,0] and [0,0,0]
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[3] = 1;
+        trie.insert(key_a, key_a);
+        trie.insert(key_b, key_b);
+        trie.flush_database();
+
+        // Only count the reads made after the nodes have been flushed
+        let storage = &trie.storage;
+        storage.storage.num_fetches.set(0);
+        for path in [vec![], vec![0], vec![0, 0]] {
+            assert!(storage.get_branch_meta(&path).is_some());
+        }
+        assert_eq!(storage.storage.num_fetches.get(), 0);
+
+        // The branch at [0,0,0] is below the cache depth, so it is read from the backend
+        assert!(storage.get_branch_meta(&[0, 0, 0]).is_some());
+        assert_eq!(storage.storage.num_fetches.get(), 1);
+    }
+
+    #[test]
+    fn flush_is_split_into_batches() {
+        let mut db = VerkleDb::<CountingKVDb>::from_path("");
+        db.set_flush_batch_size(10);
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        for i in 0..20u8 {
+            trie.insert([i; 32], [i; 32]);
+        }
+        // The flush also writes the checksum of the trie
+        let num_items = trie.storage.batch.num_items() + 1;
+        trie.flush_database();
+
+        let batch_sizes = &trie.storage.storage.batch_sizes;
+        assert!(batch_sizes.len() > 1);
+        assert!(batch_sizes.iter().all(|size| *size <= 10));
+        assert_eq!(batch_sizes.iter().sum::<usize>(), num_items);
+
+        // All of the items can still be read back from the storage
+        assert_eq!(trie.storage.storage.map.len(), num_items);
+        for i in 0..20u8 {
+            assert_eq!(trie.get([i; 32]), Some([i; 32]));
+        }
+    }
+
+    #[test]
+    fn try_get_propagates_backend_errors() {
+        let db = VerkleDb::<CountingKVDb>::from_path("");
+        // Nothing is cached, so every read after the flush goes to the backend
+        let mut trie = Trie::new_with_cache_depth(db, BasicCommitter, 0);
+
+        let key_a = [1u8; 32];
+        let key_b = [2u8; 32];
+        trie.insert(key_a, key_a);
+        trie.insert(key_b, key_b);
+        trie.flush_database();
+
+        let mut failing_key = vec![crate::database::generic::LEAF_TABLE_MARKER];
+        failing_key.extend_from_slice(&key_b);
+        trie.storage
+            .storage
+            .failing_keys
+            .borrow_mut()
+            .insert(failing_key);
+
+        assert_eq!(trie.try_get(key_a), Ok(Some(key_a)));
+        assert_eq!(trie.try_get([3u8; 32]), Ok(None));
+        assert_eq!(
+            trie.try_get(key_b),
+            Err(DbError("injected read failure".to_string()))
+        );
+    }
+
+    #[test]
+    fn remove_is_not_supported() {
+        use crate::database::WriteOnlyHigherDb;
+
+        let mut db = VerkleDb::<CountingKVDb>::from_path("");
+        assert!(db.remove_leaf([1u8; 32]).is_err());
+        assert!(db.remove_stem([1u8; 31]).is_err());
+        assert!(db.remove_branch_child(&[1]).is_err());
+    }
+
+    #[test]
+    fn insert_bytes_is_not_supported() {
+        let db = VerkleDb::<CountingKVDb>::from_path("");
+        let mut trie = Trie::new(db, BasicCommitter);
+        let root = trie.compute_root();
+
+        assert!(trie.insert_bytes([1u8; 32], b"value").is_err());
+        assert_eq!(trie.get([1u8; 32]), None);
+        assert_eq!(trie.compute_root(), root);
+    }
+
+    #[test]
+    fn warm_cache_serves_top_levels_after_cold_open() {
+        use crate::database::memory_db::MemoryDb;
+
+        let db = VerkleDb::<CountingKVDb>::from_path("");
+        let mut trie = Trie::new_with_cache_depth(db, BasicCommitter, 2);
+
+        // key_a and key_b share their first byte, so their stems are at depth 2
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[1] = 1;
+        let key_c = [1u8; 32];
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+        trie.flush_database();
+        let root = trie.root_commitment();
+
+        // Opening an existing database starts with an empty cache
+        trie.storage.cache = MemoryDb::new();
+        let read_top_levels = |trie: &Trie<VerkleDb<CountingKVDb>, BasicCommitter>| {
+            trie.storage.storage.num_fetches.set(0);
+            assert_eq!(trie.root_commitment(), root);
+            for key in [key_a, key_b, key_c] {
+                assert_eq!(trie.get(key), Some(key));
+            }
+            trie.storage.storage.num_fetches.get()
+        };
+        let cold_fetches = read_top_levels(&trie);
+        assert!(cold_fetches > 0);
+
+        trie.warm_cache(2);
+        assert_eq!(read_top_levels(&trie), 0);
+        assert_eq!(trie.storage.get_branch_children(&[]).len(), 2);
+    }
+
+    #[test]
+    fn warm_cache_is_limited_to_cache_depth() {
+        use crate::database::memory_db::MemoryDb;
+
+        let db = VerkleDb::<CountingKVDb>::from_path("");
+        let mut trie = Trie::new_with_cache_depth(db, BasicCommitter, 1);
+
+        // key_a and key_b share their first two bytes, so there is a branch node at depth 2,
+        // below the cache depth, and their stems are at depth 3
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[2] = 1;
+        let key_c = [1u8; 32];
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+        trie.flush_database();
+
+        trie.storage.cache = MemoryDb::new();
+        trie.warm_cache(3);
+        assert!(trie.storage.cache.get_branch_meta(&[0, 0]).is_none());
+        assert!(trie.storage.cache.get_stem_meta([0u8; 31]).is_none());
+
+        // Updating the stem at depth 3 must not leave a stale copy of the branch node above it in the cache
+        let mut key_d = key_a;
+        key_d[31] = 9;
+        trie.insert(key_d, key_d);
+
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        for key in [key_a, key_b, key_c, key_d] {
+            expected_trie.insert(key, key);
+        }
+        assert_eq!(trie.get(key_d), Some(key_d));
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+    }
+
+    #[test]
+    fn inserts_match_memory_db_at_any_cache_depth() {
+        use crate::database::memory_db::MemoryDb;
+
+        // The keys share prefixes of different lengths, so stems are moved below each cache depth
+        let keys: Vec<[u8; 32]> = (0..40u8)
+            .map(|i| {
+                let mut key = [0u8; 32];
+                key[0] = i % 2;
+                key[1] = i % 3;
+                key[2] = i % 5;
+                key[31] = i;
+                key
+            })
+            .collect();
+
+        for cache_depth in 0..4 {
+            let db = VerkleDb::<CountingKVDb>::from_path("");
+            let mut trie = Trie::new_with_cache_depth(db, BasicCommitter, cache_depth);
+            let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+            for (i, key) in keys.iter().enumerate() {
+                // Reopen the database half way through, with a cache warmed from the storage
+                if i == keys.len() / 2 {
+                    trie.flush_database();
+                    trie.storage.cache = MemoryDb::new();
+                    trie.warm_cache(cache_depth);
+                }
+                trie.insert(*key, *key);
+                expected_trie.insert(*key, *key);
+                assert_eq!(trie.compute_root(), expected_trie.compute_root());
+            }
+        }
+    }
+
+    #[test]
+    fn overwriting_a_flushed_leaf_matches_memory_db() {
+        use crate::database::memory_db::MemoryDb;
+
+        let db = VerkleDb::<CountingKVDb>::from_path("");
+        let mut trie = Trie::new_with_cache_depth(db, BasicCommitter, 0);
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+        let key_a = [1u8; 32];
+        let mut key_b = [1u8; 32];
+        key_b[0] = 2;
+        for key in [key_a, key_b] {
+            trie.insert(key, key);
+            expected_trie.insert(key, key);
+        }
+        // The old value of key_a is now only in the storage
+        trie.flush_database();
+
+        trie.insert(key_a, [7u8; 32]);
+        expected_trie.insert(key_a, [7u8; 32]);
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+    }
+
+    #[test]
+    fn flush_checksum_is_read_back_after_reopening() {
+        use crate::database::memory_db::MemoryDb;
+
+        let db = VerkleDb::<CountingKVDb>::from_path("");
+        let mut trie = Trie::new(db, BasicCommitter);
+        for i in 0..3u8 {
+            trie.insert([i; 32], [i; 32]);
+        }
+        // Overwriting a key does not change the number of keys
+        trie.insert([1u8; 32], [9u8; 32]);
+        trie.flush_database();
+        let root = trie.compute_root();
+
+        let db = VerkleDb {
+            storage: trie.storage.storage,
+            batch: MemoryDb::new(),
+            cache: MemoryDb::new(),
+            cache_depth: DEFAULT_CACHE_DEPTH,
+            flush_batch_size: DEFAULT_FLUSH_BATCH_SIZE,
+        };
+        let mut trie = Trie::new(db, BasicCommitter);
+        trie.warm_cache(DEFAULT_CACHE_DEPTH);
+        let checksum = trie.storage.get_flush_checksum().unwrap();
+        assert_eq!(checksum.root, root);
+        assert_eq!(checksum.num_keys, 3);
+        assert!(trie.verify_flush_integrity());
+
+        // The count carries on from the checksum of the reopened storage
+        trie.insert([3u8; 32], [3u8; 32]);
+        trie.flush_database();
+        assert_eq!(trie.storage.get_flush_checksum().unwrap().num_keys, 4);
+        assert!(trie.verify_flush_integrity());
+    }
+
+    #[test]
+    fn get_with_proof_does_not_look_up_the_key_again() {
+        let db = VerkleDb::<CountingKVDb>::from_path("");
+        let mut trie = Trie::new_with_cache_depth(db, BasicCommitter, 0);
+        let key = [1u8; 32];
+        trie.insert(key, [5u8; 32]);
+        trie.insert([2u8; 32], [2u8; 32]);
+        trie.flush_database();
+
+        let num_fetches = &trie.storage.storage.num_fetches;
+        num_fetches.set(0);
+        trie.create_verkle_proof(std::iter::once(key));
+        let proof_fetches = num_fetches.get();
+
+        num_fetches.set(0);
+        let (value, proof) = trie.get_with_proof(key);
+        assert_eq!(num_fetches.get(), proof_fetches);
+        assert_eq!(value, Some([5u8; 32]));
+
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        let (ok, _) = proof.check(vec![key], vec![value], root);
+        assert!(ok);
+    }
 }
@@ -1,4 +1,6 @@
-use super::{BranchChild, BranchMeta, ReadOnlyHigherDb, StemMeta, WriteOnlyHigherDb};
+use super::{
+    BranchChild, BranchMeta, DbError, FlushChecksum, ReadOnlyHigherDb, StemMeta, WriteOnlyHigherDb,
+};
 use std::convert::TryInto;
 use verkle_db::{BareMetalDiskDb, BareMetalKVDb, BatchDB, BatchWriter};
 
@@ -9,6 +11,8 @@ use verkle_db::{BareMetalDiskDb, BareMetalKVDb, BatchDB, BatchWriter};
 pub(crate) const LEAF_TABLE_MARKER: u8 = 0;
 pub(crate) const STEM_TABLE_MARKER: u8 = 1;
 pub(crate) const BRANCH_TABLE_MARKER: u8 = 2;
+// The flush checksum is a single entry, so the marker on its own is its key
+pub(crate) const FLUSH_CHECKSUM_MARKER: u8 = 3;
 
 // GenericBatchWriter does not write the values to disk
 // We need to flush them later on
@@ -57,6 +61,11 @@ impl<T: BatchWriter> WriteOnlyHigherDb for GenericBatchWriter<T> {
         self.inner.batch_put(&labelled_key, &meta.to_bytes());
         None
     }
+
+    fn insert_flush_checksum(&mut self, checksum: FlushChecksum) {
+        self.inner
+            .batch_put(&[FLUSH_CHECKSUM_MARKER], &checksum.to_bytes());
+    }
 }
 
 // This struct allows us to provide a default implementation of ReadOnlyHigherDB to
@@ -102,6 +111,15 @@ impl<T: BareMetalKVDb> ReadOnlyHigherDb for GenericBatchDB<T> {
             .map(|bytes| bytes.try_into().unwrap())
     }
 
+    fn try_get_leaf(&self, key: [u8; 32]) -> Result<Option<[u8; 32]>, DbError> {
+        let mut labelled_key = Vec::with_capacity(key.len() + 1);
+        labelled_key.push(LEAF_TABLE_MARKER);
+        labelled_key.extend_from_slice(&key);
+
+        let value = self.inner.try_fetch(&labelled_key)?;
+        Ok(value.map(|bytes| bytes.try_into().unwrap()))
+    }
+
     fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
         let mut labelled_key = Vec::with_capacity(stem_key.len() + 1);
         labelled_key.push(STEM_TABLE_MARKER);
@@ -174,4 +192,10 @@ impl<T: BareMetalKVDb> ReadOnlyHigherDb for GenericBatchDB<T> {
 
         children
     }
+
+    fn get_flush_checksum(&self) -> Option<FlushChecksum> {
+        self.inner
+            .fetch(&[FLUSH_CHECKSUM_MARKER])
+            .map(|bytes| FlushChecksum::from_bytes(&bytes))
+    }
 }
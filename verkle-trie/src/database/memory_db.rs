@@ -1,6 +1,10 @@
-use super::{BranchChild, Flush, ReadOnlyHigherDb, WriteOnlyHigherDb};
-use crate::database::{BranchMeta, StemMeta};
-use std::{collections::HashMap, convert::TryInto};
+use super::{BranchChild, DbError, Flush, ReadOnlyHigherDb, WriteOnlyHigherDb};
+use crate::database::{BranchMeta, FlushChecksum, StemMeta};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    sync::Arc,
+};
 
 #[derive(Debug, Clone)]
 pub struct MemoryDb {
@@ -8,6 +12,17 @@ pub struct MemoryDb {
     pub stem_table: HashMap<[u8; 31], StemMeta>,
     // TODO maybe change to use BChild and also include the index in the key (Vec<u8>, u8)
     pub branch_table: HashMap<Vec<u8>, BranchChild>,
+    // The values of any length which were inserted, keyed by the hash stored in their leaf
+    pub preimage_table: HashMap<[u8; 32], Vec<u8>>,
+    // The checksum recorded by the last flush of the trie
+    pub flush_checksum: Option<FlushChecksum>,
+    // A forked database only stores its own writes in the tables above.
+    // Reads which miss the tables fall through to the database it was forked from.
+    base: Option<Arc<MemoryDb>>,
+    // The entries of the base which were removed in this database, so that reads do not fall through to them
+    removed_leaves: HashSet<[u8; 32]>,
+    removed_stems: HashSet<[u8; 31]>,
+    removed_branch_children: HashSet<Vec<u8>>,
 }
 
 impl MemoryDb {
@@ -16,27 +31,120 @@ impl MemoryDb {
             leaf_table: HashMap::new(),
             stem_table: HashMap::new(),
             branch_table: HashMap::new(),
+            preimage_table: HashMap::new(),
+            flush_checksum: None,
+            base: None,
+            removed_leaves: HashSet::new(),
+            removed_stems: HashSet::new(),
+            removed_branch_children: HashSet::new(),
         }
     }
 
+    // Creates a database which reads from this one, but keeps its writes to itself.
+    // Entries removed from the fork are hidden from its reads, and remain in this database.
+    //
+    // The writes of this database are first moved into a base which is shared through an `Arc`,
+    // and this database then reads from that base too. So forking it again, or cloning a fork,
+    // does not copy any of the tables
+    pub fn fork(&mut self) -> MemoryDb {
+        if !self.has_no_writes() {
+            let shared = std::mem::replace(self, MemoryDb::new());
+            self.base = Some(Arc::new(shared));
+        }
+
+        MemoryDb {
+            base: self.base.clone(),
+            ..MemoryDb::new()
+        }
+    }
+
+    // Returns the number of items that were written to this database.
+    // For a forked database, this does not include the items in its base
     pub fn num_items(&self) -> usize {
-        self.leaf_table.len() + self.stem_table.len() + self.branch_table.len()
+        self.leaf_table.len()
+            + self.stem_table.len()
+            + self.branch_table.len()
+            + self.preimage_table.len()
     }
 
     pub fn clear(&mut self) {
         self.leaf_table.clear();
         self.stem_table.clear();
         self.branch_table.clear();
+        self.preimage_table.clear();
+        self.flush_checksum = None;
+        self.base = None;
+        self.removed_leaves.clear();
+        self.removed_stems.clear();
+        self.removed_branch_children.clear();
+    }
+
+    fn has_no_writes(&self) -> bool {
+        self.num_items() == 0
+            && self.flush_checksum.is_none()
+            && self.removed_leaves.is_empty()
+            && self.removed_stems.is_empty()
+            && self.removed_branch_children.is_empty()
+    }
+
+    fn get_branch_table_entry(&self, key: &[u8]) -> Option<BranchChild> {
+        match self.branch_table.get(key) {
+            Some(b_child) => Some(*b_child),
+            None => self.get_base_branch_table_entry(key),
+        }
+    }
+
+    // Inserts into the branch table and returns the old entry, which may come from the base
+    fn insert_branch_table_entry(
+        &mut self,
+        key: Vec<u8>,
+        b_child: BranchChild,
+    ) -> Option<BranchChild> {
+        if self.base.is_none() {
+            return self.branch_table.insert(key, b_child);
+        }
+        match self.branch_table.insert(key.clone(), b_child) {
+            Some(old_b_child) => Some(old_b_child),
+            None if !self.removed_branch_children.remove(&key) => {
+                self.get_base_branch_table_entry(&key)
+            }
+            None => None,
+        }
+    }
+
+    // The reads below only look at the base, they are used once an entry is missing from the tables
+    fn get_base_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        if self.removed_leaves.contains(&key) {
+            return None;
+        }
+        self.base.as_ref()?.get_leaf(key)
+    }
+
+    fn get_base_stem_meta(&self, key: [u8; 31]) -> Option<StemMeta> {
+        if self.removed_stems.contains(&key) {
+            return None;
+        }
+        self.base.as_ref()?.get_stem_meta(key)
+    }
+
+    fn get_base_branch_table_entry(&self, key: &[u8]) -> Option<BranchChild> {
+        if self.removed_branch_children.contains(key) {
+            return None;
+        }
+        self.base.as_ref()?.get_branch_table_entry(key)
     }
 }
 
 impl ReadOnlyHigherDb for MemoryDb {
     fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
-        self.stem_table.get(&stem_key).copied()
+        match self.stem_table.get(&stem_key) {
+            Some(meta) => Some(*meta),
+            None => self.get_base_stem_meta(stem_key),
+        }
     }
 
     fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
-        let branch_child = match self.branch_table.get(key) {
+        let branch_child = match self.get_branch_table_entry(key) {
             Some(b_child) => b_child,
             None => return None,
         };
@@ -46,14 +154,15 @@ impl ReadOnlyHigherDb for MemoryDb {
                 "expected branch meta data, however under this path there is a stem: {}",
                 hex::encode(stem_id)
             ),
-            BranchChild::Branch(b_meta) => Some(*b_meta),
+            BranchChild::Branch(b_meta) => Some(b_meta),
         }
     }
 
     fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
-        self.leaf_table
-            .get(&key)
-            .map(|val| val.to_vec().try_into().unwrap())
+        match self.leaf_table.get(&key) {
+            Some(val) => Some(*val),
+            None => self.get_base_leaf(key),
+        }
     }
 
     fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
@@ -63,14 +172,14 @@ impl ReadOnlyHigherDb for MemoryDb {
             let mut child = branch_id.to_vec();
             child.push(i);
 
-            let value = self.branch_table.get(&child);
+            let value = self.get_branch_table_entry(&child);
 
             // If its a stem, we return the stem_id
             // If it's a branch, we return the branch_id
             // TODO: we could return the BranchChild instead and leave the caller to do what they want with it
 
             if let Some(b_child) = value {
-                children.push((i, *b_child))
+                children.push((i, b_child))
             }
         }
 
@@ -85,10 +194,8 @@ impl ReadOnlyHigherDb for MemoryDb {
             child.push(i);
             let child: [u8; 32] = child.try_into().unwrap();
 
-            let value = self.leaf_table.get(&child);
-
-            if let Some(i_vec) = value {
-                children.push((i, i_vec.to_vec().try_into().unwrap()))
+            if let Some(value) = self.get_leaf(child) {
+                children.push((i, value))
             }
         }
 
@@ -100,30 +207,52 @@ impl ReadOnlyHigherDb for MemoryDb {
         child_index.extend_from_slice(&branch_id);
         child_index.push(index);
 
-        self.branch_table.get(&child_index).copied()
+        self.get_branch_table_entry(&child_index)
+    }
+
+    fn get_preimage(&self, hash: [u8; 32]) -> Option<Vec<u8>> {
+        match self.preimage_table.get(&hash) {
+            Some(bytes) => Some(bytes.clone()),
+            None => self.base.as_ref()?.get_preimage(hash),
+        }
+    }
+
+    fn get_flush_checksum(&self) -> Option<FlushChecksum> {
+        self.flush_checksum
+            .or_else(|| self.base.as_ref()?.get_flush_checksum())
     }
 }
 
+// Writes only go to the tables of this database. The base is only read when the
+// entry is not in the tables, so that the old value can be returned
 impl WriteOnlyHigherDb for MemoryDb {
     fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, _depth: u8) -> Option<StemMeta> {
-        self.stem_table.insert(key, meta)
+        match self.stem_table.insert(key, meta) {
+            Some(old_meta) => Some(old_meta),
+            None if self.base.is_some() && !self.removed_stems.remove(&key) => {
+                self.get_base_stem_meta(key)
+            }
+            None => None,
+        }
     }
 
     fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, _depth: u8) -> Option<BranchMeta> {
-        let b_child = match self.branch_table.insert(key, BranchChild::Branch(meta)) {
-            Some(b_child) => b_child,
-            None => return None,
-        };
-        match b_child {
-            BranchChild::Stem(_) => None, // If its a stem, we return None, this only happens in ChainInsert
-            BranchChild::Branch(b_meta) => return Some(b_meta),
+        match self.insert_branch_table_entry(key, BranchChild::Branch(meta)) {
+            Some(BranchChild::Branch(b_meta)) => Some(b_meta),
+            // If its a stem, we return None, this only happens in ChainInsert
+            Some(BranchChild::Stem(_)) | None => None,
         }
     }
 
     fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], _depth: u8) -> Option<Vec<u8>> {
-        self.leaf_table
-            .insert(key, value)
-            .map(|old_val| old_val.to_vec())
+        let old_val = match self.leaf_table.insert(key, value) {
+            Some(old_val) => Some(old_val),
+            None if self.base.is_some() && !self.removed_leaves.remove(&key) => {
+                self.get_base_leaf(key)
+            }
+            None => None,
+        };
+        old_val.map(|old_val| old_val.to_vec())
     }
 
     fn add_stem_as_branch_child(
@@ -132,8 +261,48 @@ impl WriteOnlyHigherDb for MemoryDb {
         stem_id: [u8; 31],
         _depth: u8,
     ) -> Option<BranchChild> {
-        self.branch_table
-            .insert(branch_child_id, BranchChild::Stem(stem_id))
+        self.insert_branch_table_entry(branch_child_id, BranchChild::Stem(stem_id))
+    }
+
+    fn insert_preimage(&mut self, hash: [u8; 32], bytes: Vec<u8>) -> Result<(), DbError> {
+        self.preimage_table.insert(hash, bytes);
+        Ok(())
+    }
+
+    fn insert_flush_checksum(&mut self, checksum: FlushChecksum) {
+        self.flush_checksum = Some(checksum);
+    }
+
+    // Removing an entry from a forked database hides the entry in its base
+    fn remove_leaf(&mut self, key: [u8; 32]) -> Result<Option<[u8; 32]>, DbError> {
+        let old_val = self.leaf_table.remove(&key);
+        let in_base = self.get_base_leaf(key);
+        if in_base.is_some() {
+            self.removed_leaves.insert(key);
+        }
+        Ok(old_val.or(in_base))
+    }
+
+    fn remove_stem(&mut self, key: [u8; 31]) -> Result<Option<StemMeta>, DbError> {
+        let old_meta = self.stem_table.remove(&key);
+        let in_base = self.get_base_stem_meta(key);
+        if in_base.is_some() {
+            self.removed_stems.insert(key);
+        }
+        Ok(old_meta.or(in_base))
+    }
+
+    fn remove_branch_child(
+        &mut self,
+        branch_child_id: &[u8],
+    ) -> Result<Option<BranchChild>, DbError> {
+        let old_b_child = self.branch_table.remove(branch_child_id);
+        let in_base = self.get_base_branch_table_entry(branch_child_id);
+        if in_base.is_some() {
+            self.removed_branch_children
+                .insert(branch_child_id.to_vec());
+        }
+        Ok(old_b_child.or(in_base))
     }
 }
 
@@ -148,3 +317,161 @@ impl Flush for MemoryDb {
         // tests in the Trie
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryDb;
+    use crate::database::{BranchChild, ReadOnlyHigherDb, WriteOnlyHigherDb};
+    use crate::{trie::Trie, BasicCommitter};
+    use std::sync::Arc;
+
+    #[test]
+    fn fork_does_not_modify_base() {
+        let mut base = MemoryDb::new();
+        base.insert_leaf([1u8; 32], [1u8; 32], 1);
+
+        let mut fork = base.fork();
+        assert_eq!(fork.num_items(), 0);
+        assert_eq!(fork.get_leaf([1u8; 32]), Some([1u8; 32]));
+
+        // The old value returned by a fork comes from the base
+        let old_val = fork.insert_leaf([1u8; 32], [2u8; 32], 1);
+        assert_eq!(old_val, Some([1u8; 32].to_vec()));
+        fork.insert_leaf([3u8; 32], [3u8; 32], 1);
+
+        assert_eq!(fork.get_leaf([1u8; 32]), Some([2u8; 32]));
+        assert_eq!(fork.get_leaf([3u8; 32]), Some([3u8; 32]));
+        assert_eq!(base.get_leaf([1u8; 32]), Some([1u8; 32]));
+        assert_eq!(base.get_leaf([3u8; 32]), None);
+    }
+
+    #[test]
+    fn forks_share_one_base() {
+        let mut base = MemoryDb::new();
+        base.insert_leaf([1u8; 32], [1u8; 32], 1);
+
+        let fork_a = base.fork();
+        let fork_b = base.fork();
+        assert!(Arc::ptr_eq(
+            fork_a.base.as_ref().unwrap(),
+            fork_b.base.as_ref().unwrap()
+        ));
+        // The writes were moved into the shared base, which the database still reads from
+        assert_eq!(base.num_items(), 0);
+        assert_eq!(base.get_leaf([1u8; 32]), Some([1u8; 32]));
+        assert_eq!(fork_b.get_leaf([1u8; 32]), Some([1u8; 32]));
+    }
+
+    #[test]
+    fn forked_trie_matches_fresh_trie() {
+        let keys: Vec<[u8; 32]> = (0..4u8).map(|i| [i; 32]).collect();
+
+        let mut base_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        base_trie.insert(keys[0], keys[0]);
+        base_trie.insert(keys[1], keys[1]);
+        let base_root = base_trie.compute_root();
+
+        let mut forked_trie = Trie::new(base_trie.storage.fork(), BasicCommitter);
+        forked_trie.insert(keys[1], keys[3]);
+        forked_trie.insert(keys[2], keys[2]);
+
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        expected_trie.insert(keys[0], keys[0]);
+        expected_trie.insert(keys[1], keys[3]);
+        expected_trie.insert(keys[2], keys[2]);
+
+        assert_eq!(forked_trie.compute_root(), expected_trie.compute_root());
+        assert_eq!(base_trie.compute_root(), base_root);
+        assert_eq!(base_trie.get(keys[1]), Some(keys[1]));
+    }
+
+    #[test]
+    fn get_branch_children_enumerates_populated_slots() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+        // key_a and key_b share their first byte, so the root has a branch at index 0
+        // and a stem at index 5. The branch at [0] has stems at index 0 and 1
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[1] = 1;
+        let key_c = [5u8; 32];
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+
+        let mut root_children = trie.storage.get_branch_children(&[]);
+        root_children.sort_by_key(|(index, _)| *index);
+        assert_eq!(root_children.len(), 2);
+        assert!(matches!(root_children[0], (0, BranchChild::Branch(_))));
+        assert!(matches!(root_children[1], (5, BranchChild::Stem(stem)) if stem == [5u8; 31]));
+
+        let mut children = trie.storage.get_branch_children(&[0]);
+        children.sort_by_key(|(index, _)| *index);
+        assert_eq!(children.len(), 2);
+        assert!(matches!(children[0], (0, BranchChild::Stem(stem)) if stem == [0u8; 31]));
+        assert!(matches!(children[1], (1, BranchChild::Stem(stem)) if stem[1] == 1));
+
+        assert!(trie.storage.get_branch_children(&[5]).is_empty());
+    }
+
+    #[test]
+    fn remove_entries() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let key_a = [0u8; 32];
+        let mut key_b = key_a;
+        key_b[31] = 1;
+        trie.insert(key_a, [1u8; 32]);
+        trie.insert(key_b, [2u8; 32]);
+        let stem = [0u8; 31];
+
+        let db = &mut trie.storage;
+        assert_eq!(db.remove_leaf(key_a).unwrap(), Some([1u8; 32]));
+        assert_eq!(db.get_leaf(key_a), None);
+        assert_eq!(db.get_stem_children(stem), vec![(1, [2u8; 32])]);
+        assert_eq!(db.remove_leaf(key_a).unwrap(), None);
+
+        assert!(db.remove_stem(stem).unwrap().is_some());
+        assert!(db.get_stem_meta(stem).is_none());
+        assert!(db.remove_stem(stem).unwrap().is_none());
+
+        assert!(
+            matches!(db.remove_branch_child(&[0]).unwrap(), Some(BranchChild::Stem(s)) if s == stem)
+        );
+        assert!(db.get_branch_child(&[], 0).is_none());
+        assert!(db.get_branch_children(&[]).is_empty());
+        assert!(db.remove_branch_child(&[0]).unwrap().is_none());
+    }
+
+    #[test]
+    fn fork_removes_base_entries() {
+        let stem = [1u8; 31];
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.insert([1u8; 32], [1u8; 32]);
+        let mut base = trie.storage;
+
+        let mut fork = base.fork();
+        fork.insert_leaf([1u8; 32], [2u8; 32], 1);
+        assert_eq!(fork.remove_leaf([1u8; 32]).unwrap(), Some([2u8; 32]));
+        assert!(fork.remove_stem(stem).unwrap().is_some());
+        assert!(fork.remove_branch_child(&[1]).unwrap().is_some());
+
+        // The fork does not read the removed entries from the base
+        assert_eq!(fork.get_leaf([1u8; 32]), None);
+        assert!(fork.get_stem_meta(stem).is_none());
+        assert!(fork.get_branch_child(&[], 1).is_none());
+        assert!(fork.get_branch_children(&[]).is_empty());
+        assert_eq!(fork.remove_leaf([1u8; 32]).unwrap(), None);
+
+        // A fork of the fork does not see them either
+        assert_eq!(fork.fork().get_leaf([1u8; 32]), None);
+
+        // The base is unchanged
+        assert_eq!(base.get_leaf([1u8; 32]), Some([1u8; 32]));
+        assert!(base.get_stem_meta(stem).is_some());
+        assert!(base.get_branch_child(&[], 1).is_some());
+
+        // Inserting a removed entry again does not return the value in the base
+        assert_eq!(fork.insert_leaf([1u8; 32], [3u8; 32], 1), None);
+        assert_eq!(fork.get_leaf([1u8; 32]), Some([3u8; 32]));
+    }
+}
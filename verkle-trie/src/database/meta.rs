@@ -118,6 +118,34 @@ impl BranchMeta {
     }
 }
 
+// The root and the number of keys in the trie, recorded by the last flush
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushChecksum {
+    pub root: Fr,
+    pub num_keys: u64,
+}
+
+impl FlushChecksum {
+    pub fn from_bytes(bytes: &[u8]) -> FlushChecksum {
+        assert_eq!(bytes.len(), 32 + 8);
+
+        use ark_serialize::CanonicalDeserialize;
+
+        let root = Fr::deserialize_uncompressed(&bytes[0..32]).unwrap();
+        let num_keys = u64::from_le_bytes(bytes[32..].try_into().unwrap());
+
+        FlushChecksum { root, num_keys }
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32 + 8);
+
+        bytes.extend(scalar_to_array(&self.root));
+        bytes.extend(self.num_keys.to_le_bytes());
+
+        bytes
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Meta {
     Stem(StemMeta),
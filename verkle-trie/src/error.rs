@@ -0,0 +1,107 @@
+use crate::database::DbError;
+use crate::proof::VerificationError;
+
+// The errors returned by the trie and the proofs, so that they can be combined using `?`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerkleError {
+    // The storage failed to read or write a value
+    Db(DbError),
+    // A proof was rejected by the verifier
+    Verification(VerificationError),
+    // A proof was requested for more keys than the trie allows, see `Trie::set_max_proof_keys`
+    TooManyKeys { num_keys: usize, max_keys: usize },
+    // A state diff deletes a key which is in the trie, the trie does not support deletions yet
+    DeleteNotSupported { key: [u8; 32] },
+}
+
+impl From<DbError> for VerkleError {
+    fn from(err: DbError) -> Self {
+        VerkleError::Db(err)
+    }
+}
+
+impl From<VerificationError> for VerkleError {
+    fn from(err: VerificationError) -> Self {
+        VerkleError::Verification(err)
+    }
+}
+
+impl std::fmt::Display for VerkleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerkleError::Db(err) => write!(f, "{}", err),
+            VerkleError::Verification(err) => write!(f, "proof verification failed: {:?}", err),
+            VerkleError::TooManyKeys { num_keys, max_keys } => write!(
+                f,
+                "cannot create a proof for {} keys, the maximum is {}",
+                num_keys, max_keys
+            ),
+            VerkleError::DeleteNotSupported { key } => write!(
+                f,
+                "cannot delete key {}, deletions are not supported",
+                hex::encode(key)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerkleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::VerkleError;
+    use crate::database::memory_db::MemoryDb;
+    use crate::database::{DbError, ReadOnlyHigherDb};
+    use crate::proof::VerificationError;
+    use crate::{trie::Trie, BasicCommitter};
+
+    // Reads the values for the keys and checks them against a proof, as a stateless client would
+    fn read_and_verify(
+        trie: &Trie<MemoryDb, BasicCommitter>,
+        keys: Vec<[u8; 32]>,
+        proof_keys: Vec<[u8; 32]>,
+    ) -> Result<Vec<Option<[u8; 32]>>, VerkleError> {
+        let values = keys
+            .iter()
+            .map(|key| trie.try_get(*key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        let proof = trie.create_verkle_proof(proof_keys.into_iter());
+        proof.verify(keys, values.clone(), root)?;
+
+        Ok(values)
+    }
+
+    #[test]
+    fn errors_convert_into_verkle_error() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let keys: Vec<_> = (0..2u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+
+        let values = read_and_verify(&trie, keys.clone(), keys.clone());
+        assert_eq!(values, Ok(vec![Some(keys[0]), Some(keys[1])]));
+
+        // The proof only has a depth for one of the two stems
+        let result = read_and_verify(&trie, keys.clone(), keys[0..1].to_vec());
+        assert_eq!(
+            result,
+            Err(VerkleError::Verification(
+                VerificationError::HintLengthMismatch {
+                    num_stems: 2,
+                    num_depths: 1,
+                    num_extension_present: 1
+                }
+            ))
+        );
+
+        let err: VerkleError = DbError("disk unavailable".to_string()).into();
+        assert_eq!(
+            err,
+            VerkleError::Db(DbError("disk unavailable".to_string()))
+        );
+        assert_eq!(err.to_string(), "database error: disk unavailable");
+    }
+}
@@ -1,20 +1,28 @@
 #[deny(unreachable_patterns)]
 mod byte_arr;
 pub mod database;
+mod error;
 pub mod precompute;
 pub mod proof;
+pub mod remote;
 pub mod trie;
 
+pub use error::VerkleError;
+
 pub type Key = [u8; 32];
 pub type Value = [u8; 32];
 
 use ark_ec::ProjectiveCurve;
-use ark_ff::{PrimeField, Zero};
+use ark_ff::{One, PrimeField, Zero};
 use ark_serialize::CanonicalSerialize;
-use bandersnatch::{EdwardsProjective, Fr};
+use bandersnatch::{EdwardsAffine, EdwardsProjective, Fr};
 
 pub const FLUSH_BATCH: u32 = 20_000;
 
+// The maximum depth of a stem. A stem is 31 bytes, so there can be at most 31 branch nodes
+// on the path from the root to a stem, and the deepest branch node has a path of 30 bytes
+pub(crate) const MAX_DEPTH: u8 = 31;
+
 pub trait TrieTrait {
     /// Inserts multiple values into the trie, returning the recomputed root.
     /// If the number of items is below FLUSH_BATCH, they will be persisted
@@ -53,29 +61,83 @@ pub trait TrieTrait {
 pub trait Committer {
     // Commit to a lagrange polynomial, evaluations.len() must equal the size of the SRS at the moment
     fn commit_lagrange(&self, evaluations: &[Fr]) -> EdwardsProjective;
+    // Commit to a lagrange polynomial with fewer evaluations than the size of the SRS,
+    // the missing evaluations are treated as zero
+    fn commit_lagrange_padded(&self, evaluations: &[Fr]) -> EdwardsProjective {
+        assert!(
+            evaluations.len() <= SRS.len(),
+            "cannot commit to {} evaluations, the domain size is {}",
+            evaluations.len(),
+            SRS.len()
+        );
+        let mut padded_evaluations = evaluations.to_vec();
+        padded_evaluations.resize(SRS.len(), Fr::zero());
+        self.commit_lagrange(&padded_evaluations)
+    }
     // compute value * G for a specific generator in the SRS
     fn scalar_mul(&self, value: Fr, lagrange_index: usize) -> EdwardsProjective;
+    // Returns the generator used for the evaluation at `lagrange_index`.
+    // This is the commitment to the polynomial which is one at `lagrange_index` and zero elsewhere
+    fn srs_point(&self, lagrange_index: usize) -> EdwardsAffine {
+        self.scalar_mul(Fr::one(), lagrange_index).into_affine()
+    }
+    // The commitment to the zero polynomial, this is also the result of
+    // scalar_mul when the value is zero
+    fn identity_commitment(&self) -> EdwardsProjective {
+        EdwardsProjective::zero()
+    }
 }
-// A Basic Commit struct to be used in tests.
+// A Basic Commit struct to be used in tests and scripts.
 // In production, we will use the Precomputed points
-pub(crate) struct BasicCommitter;
+#[derive(Debug, Clone, Copy)]
+pub struct BasicCommitter;
+
+impl BasicCommitter {
+    // The basic committer uses the global SRS, which is computed on first use.
+    // `SRS` is a `Lazy` from once_cell::sync, so concurrent first uses do not race.
+    // Creating the committer computes the SRS, so that the first commitment does not pay for it
+    pub fn new() -> BasicCommitter {
+        Lazy::force(&SRS);
+        BasicCommitter
+    }
+}
+
+impl Default for BasicCommitter {
+    fn default() -> Self {
+        BasicCommitter::new()
+    }
+}
 impl Committer for BasicCommitter {
     fn commit_lagrange(&self, evaluations: &[Fr]) -> EdwardsProjective {
-        let mut res = EdwardsProjective::zero();
+        // Like the precomputed committer, zero evaluations are skipped, so committing to an
+        // empty node, where every evaluation is zero, returns the identity without any multiplications
+        let mut res = self.identity_commitment();
         for (val, point) in evaluations.iter().zip(SRS.iter()) {
+            if val.is_zero() {
+                continue;
+            }
             res += point.mul(val.into_repr())
         }
         res
     }
 
     fn scalar_mul(&self, value: Fr, lagrange_index: usize) -> EdwardsProjective {
+        // Deltas for unchanged slots are zero, so we skip the scalar multiplication
+        if value.is_zero() {
+            return self.identity_commitment();
+        }
         SRS[lagrange_index].mul(value.into_repr())
     }
 }
 
+// Empty nodes commit to the identity point, so its hash is known without serialising it
+pub(crate) fn identity_hash() -> Fr {
+    Fr::zero()
+}
+
 pub(crate) fn group_to_field(point: &EdwardsProjective) -> Fr {
     if point.is_zero() {
-        return Fr::zero();
+        return identity_hash();
     }
     let mut bytes = [0u8; 32];
     point
@@ -84,6 +146,31 @@ pub(crate) fn group_to_field(point: &EdwardsProjective) -> Fr {
     Fr::from_le_bytes_mod_order(&bytes)
 }
 
+// A commitment which can be used as a key in a HashMap or BTreeMap.
+//
+// EdwardsProjective cannot be used as a key itself. Its `Hash` is derived from the projective
+// coordinates, whereas two points are equal if they are the same point on the curve, so equal
+// commitments can have different hashes. This holds the compressed form of the commitment,
+// which is the same for equal points
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CommitmentKey([u8; 32]);
+
+impl CommitmentKey {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<EdwardsProjective> for CommitmentKey {
+    fn from(commitment: EdwardsProjective) -> CommitmentKey {
+        let mut bytes = [0u8; 32];
+        commitment
+            .serialize(&mut bytes[..])
+            .expect("could not serialise point into a 32 byte array");
+        CommitmentKey(bytes)
+    }
+}
+
 // TODO: Possible optimisation. This means we never allocate for paths
 use smallvec::SmallVec;
 pub type SmallVec32 = SmallVec<[u8; 32]>;
@@ -91,10 +178,14 @@ pub type SmallVec32 = SmallVec<[u8; 32]>;
 use once_cell::sync::Lazy;
 
 // TODO: change this into a constant
-pub(crate) fn two_pow_128() -> Fr {
+// 2^128 is added to the low 16 bytes of every leaf value, so we only compute it once
+static TWO_POW_128: Lazy<Fr> = Lazy::new(|| {
     let mut arr = [0u8; 17];
     arr[0] = 1;
     Fr::from_be_bytes_mod_order(&arr)
+});
+pub(crate) fn two_pow_128() -> Fr {
+    *TWO_POW_128
 }
 
 // TODO: This is insecure, it is used to test interopability with the python code
@@ -126,6 +217,118 @@ pub static SRS: Lazy<[EdwardsProjective; 256]> = Lazy::new(|| {
 //     points
 // });
 
+#[test]
+fn zero_scalar_mul_is_identity() {
+    let committer = BasicCommitter;
+    for index in [0, 1, 255] {
+        let result = committer.scalar_mul(Fr::zero(), index);
+        assert_eq!(result, committer.identity_commitment());
+        assert!(result.is_zero());
+    }
+
+    let table = precompute::PrecomputeLagrange::precompute(&[SRS[0].into_affine()]);
+    let result = (&table).scalar_mul(Fr::zero(), 0);
+    assert_eq!(result, (&table).identity_commitment());
+}
+
+#[test]
+fn zero_commit_lagrange_is_identity() {
+    let committer = BasicCommitter;
+    let result = committer.commit_lagrange(&[Fr::zero(); 256]);
+    assert_eq!(result, committer.identity_commitment());
+    assert!(result.is_zero());
+    assert!(committer.commit_lagrange_padded(&[]).is_zero());
+
+    let points: Vec<_> = SRS[0..4].iter().map(|point| point.into_affine()).collect();
+    let table = precompute::PrecomputeLagrange::precompute(&points);
+    assert!((&table).commit_lagrange(&[Fr::zero(); 4]).is_zero());
+
+    // Zero evaluations are skipped, but do not change the commitment to the other evaluations
+    let mut evaluations = [Fr::zero(); 4];
+    evaluations[2] = Fr::from(7u64);
+    assert_eq!(
+        committer.commit_lagrange(&evaluations),
+        committer.scalar_mul(Fr::from(7u64), 2)
+    );
+    assert_eq!(
+        (&table).commit_lagrange(&evaluations),
+        committer.commit_lagrange(&evaluations)
+    );
+}
+
+#[test]
+fn precomputed_scalar_mul_matches_basic_committer() {
+    let points: Vec<_> = SRS[0..4].iter().map(|point| point.into_affine()).collect();
+    let table = precompute::PrecomputeLagrange::precompute(&points);
+
+    // Repeated commitments at the same index reuse its table
+    let committer = BasicCommitter;
+    for index in 0..4 {
+        for value in [Fr::from(1u64), Fr::from(u64::MAX), -Fr::from(3u64)] {
+            assert_eq!(
+                (&table).scalar_mul(value, index),
+                committer.scalar_mul(value, index)
+            );
+        }
+    }
+}
+
+#[test]
+fn basic_committers_are_interchangeable() {
+    let value = Fr::from(42u64);
+    let committer_a = BasicCommitter::new();
+    let committer_b = BasicCommitter::default();
+    for index in [0, 1, 255] {
+        assert_eq!(
+            committer_a.scalar_mul(value, index),
+            committer_b.scalar_mul(value, index)
+        );
+    }
+}
+
+#[test]
+fn srs_point_matches_srs() {
+    // The stem commitment always includes 1 * G_0, see `update_stem_table`.
+    // G_0 is the generator of the prime order subgroup
+    use ark_ec::AffineCurve;
+
+    let committer = BasicCommitter;
+    assert_eq!(
+        committer.srs_point(0),
+        EdwardsAffine::prime_subgroup_generator()
+    );
+    assert_eq!(committer.srs_point(0), SRS[0].into_affine());
+    assert_eq!(committer.srs_point(255), SRS[255].into_affine());
+
+    let table = precompute::PrecomputeLagrange::precompute(&[SRS[0].into_affine()]);
+    assert_eq!((&table).srs_point(0), committer.srs_point(0));
+}
+
+#[test]
+fn commit_lagrange_padded() {
+    let a = Fr::from(5u64);
+    let b = -Fr::from(7u64);
+
+    let mut evaluations = vec![Fr::zero(); 256];
+    evaluations[0] = a;
+    evaluations[1] = b;
+
+    let committer = BasicCommitter;
+    assert_eq!(
+        committer.commit_lagrange_padded(&[a, b]),
+        committer.commit_lagrange(&evaluations)
+    );
+}
+
+#[test]
+fn identity_hash_matches_group_to_field() {
+    // group_to_field short-circuits the identity, so we serialise it here to check the constant
+    let mut bytes = [0u8; 32];
+    EdwardsProjective::zero().serialize(&mut bytes[..]).unwrap();
+    assert_eq!(identity_hash(), Fr::from_le_bytes_mod_order(&bytes));
+    assert_eq!(identity_hash(), group_to_field(&EdwardsProjective::zero()));
+}
+
 #[test]
 fn consistent_group_to_field() {
     // In python this is called commitment_to_field
@@ -140,3 +343,39 @@ fn consistent_group_to_field() {
         .unwrap();
     assert_eq!(hex::encode(&bytes), expected);
 }
+
+#[test]
+fn commitment_keys_of_equal_commitments_collide() {
+    use ark_ec::AffineCurve;
+    use std::collections::HashMap;
+
+    let committer = BasicCommitter;
+    let a = committer.scalar_mul(Fr::from(3u64), 0) + committer.scalar_mul(Fr::from(4u64), 1);
+    // The same point, with different projective coordinates
+    let a_normalised = a.into_affine().into_projective();
+    assert_eq!(a, a_normalised);
+    assert_ne!(a.z, a_normalised.z);
+    let b = committer.scalar_mul(Fr::from(5u64), 2);
+
+    let mut map = HashMap::new();
+    map.insert(CommitmentKey::from(a), "a");
+    map.insert(CommitmentKey::from(b), "b");
+    assert_eq!(map.get(&CommitmentKey::from(a_normalised)), Some(&"a"));
+    assert_eq!(map.len(), 2);
+
+    map.insert(CommitmentKey::from(a_normalised), "a_normalised");
+    assert_eq!(map.len(), 2);
+    assert_eq!(map[&CommitmentKey::from(a)], "a_normalised");
+    assert_eq!(map[&CommitmentKey::from(b)], "b");
+
+    assert_eq!(
+        CommitmentKey::from(EdwardsProjective::zero()).as_bytes(),
+        &[0u8; 32]
+    );
+}
+
+#[test]
+fn cached_two_pow_128() {
+    use ark_ff::Field;
+    assert_eq!(two_pow_128(), Fr::from(2u64).pow([128u64]));
+}
@@ -42,6 +42,10 @@ impl<'a> Committer for &'a PrecomputeLagrange {
     }
 
     fn scalar_mul(&self, value: Fr, lagrange_index: usize) -> EdwardsProjective {
+        // Deltas for unchanged slots are zero, so we skip the table lookups
+        if value.is_zero() {
+            return self.identity_commitment();
+        }
         let table = &self.inner[lagrange_index];
 
         let bytes = ark_ff::to_bytes!(value).unwrap();
@@ -1,3 +1,5 @@
+use ark_ff::Zero;
+use ark_serialize::CanonicalDeserialize;
 use bandersnatch::{EdwardsProjective, Fr};
 use std::collections::{BTreeMap, BTreeSet};
 
@@ -5,6 +7,8 @@ mod key_path_finder;
 mod opening_data;
 pub(crate) mod prover;
 
+pub use opening_data::BranchPolynomialCache;
+
 pub(crate) mod verifier;
 
 // Given a polynomial `f`
@@ -34,6 +38,7 @@ pub struct VerifierQuery {
 // TODO Need to check if this is fine with the Verifier algorithm
 // TODO Note KeyState holds more information
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) enum ExtPresent {
     // This means that there is no extensions present at all
     // this corresponds to the case of when the key is not in the trie
@@ -50,6 +55,7 @@ pub(crate) enum ExtPresent {
 
 // Auxillary data that the verifier needs in order to reconstruct the verifier queries
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VerificationHint {
     // depths and extension present status sorted by stem
     depths: Vec<u8>,
@@ -67,31 +73,244 @@ pub struct UpdateHint {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VerkleProof {
     verification_hint: VerificationHint,
     // Commitments sorted by their paths and then their indices
     // The root is taken out when we serialise, so the verifier does not receive it
+    #[cfg_attr(feature = "serde", serde(with = "serde_commitments"))]
     comms_sorted: Vec<EdwardsProjective>,
 }
 
+// Decodes a commitment from its canonical compressed 32 byte form.
+// This checks that the point is on the curve and in the prime order subgroup
+pub(crate) fn deserialize_commitment(
+    bytes: &[u8; 32],
+) -> Result<EdwardsProjective, ark_serialize::SerializationError> {
+    EdwardsProjective::deserialize(&bytes[..])
+}
+
+// Group elements do not implement serde, so we serialise each commitment
+// using its canonical compressed 32 byte form
+#[cfg(feature = "serde")]
+mod serde_commitments {
+    use ark_serialize::CanonicalSerialize;
+    use bandersnatch::EdwardsProjective;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        comms: &[EdwardsProjective],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let comms_bytes: Vec<[u8; 32]> = comms
+            .iter()
+            .map(|comm| {
+                let mut bytes = [0u8; 32];
+                comm.serialize(&mut bytes[..])
+                    .expect("could not serialise point into a 32 byte array");
+                bytes
+            })
+            .collect();
+        comms_bytes.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<EdwardsProjective>, D::Error> {
+        let comms_bytes = Vec::<[u8; 32]>::deserialize(deserializer)?;
+        comms_bytes
+            .iter()
+            .map(|bytes| super::deserialize_commitment(bytes).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+// The reason that a proof was rejected by the verifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerificationError {
+    // The number of keys and values that the proof is being checked against differ
+    KeyValueLengthMismatch {
+        num_keys: usize,
+        num_values: usize,
+    },
+    // A proof for an empty set of keys should also be empty
+    NonEmptyProofForNoKeys,
+    // The verification hint should have a depth and an extension status for each stem
+    HintLengthMismatch {
+        num_stems: usize,
+        num_depths: usize,
+        num_extension_present: usize,
+    },
+    // A stem cannot be at depth zero, nor deeper than the length of a stem
+    DepthOutOfRange {
+        depth: u8,
+    },
+    // The proof shows that the key is not in the trie, however a value was given for it
+    ValueForAbsentKey {
+        key: [u8; 32],
+    },
+    // The key's path leads to a different stem, but the proof does not say which stem
+    MissingOtherStem {
+        key: [u8; 32],
+        depth: u8,
+    },
+    // The key's path leads to a different stem, but the proof has more than one candidate for it
+    AmbiguousOtherStem {
+        key: [u8; 32],
+        depth: u8,
+    },
+    // The proof has stems which are not on the path of any key
+    UnusedOtherStems,
+    // The number of commitments in the proof does not match the number of nodes being opened
+    CommitmentCountMismatch {
+        expected: usize,
+        got: usize,
+    },
+    // The compressed root could not be decoded into a point on the curve
+    InvalidRoot,
+    // A batch of proofs should be checked against one list of keys and one list of values per proof
+    BatchLengthMismatch {
+        num_proofs: usize,
+        num_claims: usize,
+    },
+}
+
+// Statistics about the creation of a proof, to help understand the cost of proving a set of keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofMetrics {
+    // The number of keys that were given, including duplicates
+    pub num_keys: usize,
+    // The number of distinct branch nodes on the paths of the keys, including the root
+    pub num_branches: usize,
+    // The number of commitments in the proof, the root is not included
+    pub num_commitments: usize,
+    // The time taken to create the proof
+    pub duration: std::time::Duration,
+}
+
 impl VerkleProof {
+    // The number of commitments that the proof carries, the root is not included
+    pub fn num_commitments(&self) -> usize {
+        self.comms_sorted.len()
+    }
+
     pub fn check(
         self,
         keys: Vec<[u8; 32]>,
         values: Vec<Option<[u8; 32]>>,
         root: EdwardsProjective,
     ) -> (bool, Option<UpdateHint>) {
-        let queries_update_hint = verifier::create_verifier_queries(self, keys, values, root);
+        match self.verify(keys, values, root) {
+            Ok(update_hint) => (true, Some(update_hint)),
+            Err(_) => (false, None),
+        }
+    }
 
-        let (queries, update_hint) = match queries_update_hint {
-            Some((queries, update_hint)) => (queries, update_hint),
-            None => return (false, None),
-        };
+    // Returns true if the structure of the proof corresponds to the given keys.
+    // That is, the proof has a depth and extension status for each of the keys' stems
+    // and a commitment for each node on the keys' paths.
+    //
+    // This does not check any values, nor the root, so it can be used to check that a proof
+    // was created for a set of keys before the values are known
+    pub fn covers_keys(&self, keys: &[[u8; 32]]) -> bool {
+        // Absent values are accepted for every extension status, so only the structure is checked
+        let values = vec![None; keys.len()];
+        let root = EdwardsProjective::zero();
+        verifier::create_verifier_queries(self.clone(), keys.to_vec(), values, root).is_ok()
+    }
+
+    // Same as `check`, however it returns the reason that the proof was rejected
+    pub fn verify(
+        self,
+        keys: Vec<[u8; 32]>,
+        values: Vec<Option<[u8; 32]>>,
+        root: EdwardsProjective,
+    ) -> Result<UpdateHint, VerificationError> {
+        let (_queries, update_hint) = verifier::create_verifier_queries(self, keys, values, root)?;
 
         // TODO: Verify queries when IPA is added
 
-        (true, Some(update_hint))
+        Ok(update_hint)
     }
+
+    // Same as `verify`, however the root commitment is given in its compressed 32 byte form,
+    // such as the root in a block header
+    pub fn verify_with_compressed_root(
+        self,
+        keys: Vec<[u8; 32]>,
+        values: Vec<Option<[u8; 32]>>,
+        root: [u8; 32],
+    ) -> Result<UpdateHint, VerificationError> {
+        let root = deserialize_commitment(&root).map_err(|_| VerificationError::InvalidRoot)?;
+        self.verify(keys, values, root)
+    }
+}
+
+// Independent proofs over disjoint sets of keys, which are carried and serialised as one object
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerkleProofBatch {
+    proofs: Vec<VerkleProof>,
+}
+
+impl VerkleProofBatch {
+    pub fn new() -> VerkleProofBatch {
+        VerkleProofBatch::default()
+    }
+
+    pub fn push(&mut self, proof: VerkleProof) {
+        self.proofs.push(proof)
+    }
+
+    pub fn len(&self) -> usize {
+        self.proofs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proofs.is_empty()
+    }
+
+    // Verifies each proof against its keys and values, so `keys[i]` and `values[i]` are
+    // checked against the i'th proof that was pushed.
+    // All of the proofs must have been created against the same root.
+    //
+    // TODO: Once IPA is added, the queries for all of the proofs can be opened in one multiproof
+    pub fn verify(
+        self,
+        keys: Vec<Vec<[u8; 32]>>,
+        values: Vec<Vec<Option<[u8; 32]>>>,
+        root: EdwardsProjective,
+    ) -> Result<Vec<UpdateHint>, VerificationError> {
+        if self.proofs.len() != keys.len() || self.proofs.len() != values.len() {
+            return Err(VerificationError::BatchLengthMismatch {
+                num_proofs: self.proofs.len(),
+                num_claims: keys.len().min(values.len()),
+            });
+        }
+
+        self.proofs
+            .into_iter()
+            .zip(keys)
+            .zip(values)
+            .map(|((proof, keys), values)| proof.verify(keys, values, root))
+            .collect()
+    }
+}
+
+// Verifies a proof using only the root commitment and the key-value pairs that it claims to prove.
+// A stateless verifier does not have a trie, so this is not a method on `Trie`
+pub fn verify_proof(
+    root: EdwardsProjective,
+    proof: VerkleProof,
+    claimed: &[([u8; 32], [u8; 32])],
+) -> bool {
+    let (keys, values) = claimed
+        .iter()
+        .map(|(key, value)| (*key, Some(*value)))
+        .unzip();
+
+    let (ok, _) = proof.check(keys, values, root);
+    ok
 }
 
 #[cfg(test)]
@@ -123,6 +342,628 @@ mod test {
         assert!(ok);
     }
 
+    #[test]
+    fn empty_proof_checks_empty_keys() {
+        use crate::proof::{VerificationHint, VerkleProof};
+        use std::collections::BTreeSet;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+        let key = [1u8; 32];
+        trie.insert(key, key);
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        let empty_proof = VerkleProof {
+            verification_hint: VerificationHint {
+                depths: Vec::new(),
+                extension_present: Vec::new(),
+                diff_stem_no_proof: BTreeSet::new(),
+            },
+            comms_sorted: Vec::new(),
+        };
+        let (ok, _) = empty_proof.check(Vec::new(), Vec::new(), root);
+        assert!(ok);
+
+        // A non-empty proof cannot be used to prove an empty set of keys
+        let proof = prover::create_verkle_proof(&trie.storage, vec![key]);
+        let (ok, _) = proof.check(Vec::new(), Vec::new(), root);
+        assert!(!ok);
+    }
+
+    #[test]
+    fn mismatched_proof_sizes_are_rejected() {
+        use crate::proof::VerificationError;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let mut keys = Vec::new();
+        for i in 0..=3 {
+            let mut key_0 = [0u8; 32];
+            key_0[0] = i;
+            keys.push(key_0);
+            trie.insert(key_0, key_0);
+        }
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
+
+        let mut missing_comm = proof.clone();
+        missing_comm.comms_sorted.pop();
+        assert_eq!(
+            missing_comm
+                .verify(keys.clone(), values.clone(), root)
+                .err(),
+            Some(VerificationError::CommitmentCountMismatch {
+                expected: proof.comms_sorted.len() + 1,
+                got: proof.comms_sorted.len()
+            })
+        );
+
+        let mut missing_depth = proof.clone();
+        missing_depth.verification_hint.depths.pop();
+        assert_eq!(
+            missing_depth
+                .verify(keys.clone(), values.clone(), root)
+                .err(),
+            Some(VerificationError::HintLengthMismatch {
+                num_stems: 4,
+                num_depths: 3,
+                num_extension_present: 4
+            })
+        );
+
+        let mut deep_depth = proof.clone();
+        deep_depth.verification_hint.depths[0] = 32;
+        assert_eq!(
+            deep_depth.verify(keys.clone(), values.clone(), root).err(),
+            Some(VerificationError::DepthOutOfRange { depth: 32 })
+        );
+
+        assert_eq!(
+            proof.verify(keys, values[0..2].to_vec(), root).err(),
+            Some(VerificationError::KeyValueLengthMismatch {
+                num_keys: 4,
+                num_values: 2
+            })
+        );
+    }
+
+    #[test]
+    fn asymmetric_empty_inputs_are_rejected() {
+        use crate::proof::{VerificationError, VerificationHint, VerkleProof};
+        use std::collections::BTreeSet;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+        let key = [1u8; 32];
+        trie.insert(key, key);
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        let proof = prover::create_verkle_proof(&trie.storage, vec![key]);
+
+        let empty_hint = VerificationHint {
+            depths: Vec::new(),
+            extension_present: Vec::new(),
+            diff_stem_no_proof: BTreeSet::new(),
+        };
+
+        // No keys, but the proof has commitments
+        let only_comms = VerkleProof {
+            verification_hint: empty_hint.clone(),
+            comms_sorted: proof.comms_sorted.clone(),
+        };
+        assert_eq!(
+            only_comms.verify(Vec::new(), Vec::new(), root).err(),
+            Some(VerificationError::NonEmptyProofForNoKeys)
+        );
+
+        // No keys, but the proof has a verification hint
+        let only_hint = VerkleProof {
+            verification_hint: proof.verification_hint.clone(),
+            comms_sorted: Vec::new(),
+        };
+        assert_eq!(
+            only_hint.clone().verify(Vec::new(), Vec::new(), root).err(),
+            Some(VerificationError::NonEmptyProofForNoKeys)
+        );
+
+        // Keys, but the proof is empty
+        let empty_proof = VerkleProof {
+            verification_hint: empty_hint,
+            comms_sorted: Vec::new(),
+        };
+        assert_eq!(
+            empty_proof.verify(vec![key], vec![Some(key)], root).err(),
+            Some(VerificationError::HintLengthMismatch {
+                num_stems: 1,
+                num_depths: 0,
+                num_extension_present: 0
+            })
+        );
+
+        // Keys and a verification hint, but no commitments other than the root
+        assert_eq!(
+            only_hint.verify(vec![key], vec![Some(key)], root).err(),
+            Some(VerificationError::CommitmentCountMismatch {
+                expected: proof.comms_sorted.len() + 1,
+                got: 1
+            })
+        );
+
+        // No keys, but there are values
+        assert_eq!(
+            proof.verify(Vec::new(), vec![Some(key)], root).err(),
+            Some(VerificationError::KeyValueLengthMismatch {
+                num_keys: 0,
+                num_values: 1
+            })
+        );
+    }
+
+    #[test]
+    fn verify_with_compressed_root() {
+        use crate::proof::VerificationError;
+        use ark_serialize::CanonicalSerialize;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let keys = vec![[0u8; 32], [1u8; 32]];
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+        let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        let mut compressed_root = [0u8; 32];
+        root.serialize(&mut compressed_root[..]).unwrap();
+
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
+        let from_point = proof.clone().verify(keys.clone(), values.clone(), root);
+        let from_bytes = proof.clone().verify_with_compressed_root(
+            keys.clone(),
+            values.clone(),
+            compressed_root,
+        );
+
+        // The verifier uses the root as the commitment at the empty path
+        let from_point = from_point.unwrap().commitments_by_path;
+        let from_bytes = from_bytes.unwrap().commitments_by_path;
+        assert_eq!(from_point, from_bytes);
+
+        // Not every 32 byte string is a valid point
+        assert_eq!(
+            proof
+                .verify_with_compressed_root(keys, values, [0xff; 32])
+                .err(),
+            Some(VerificationError::InvalidRoot)
+        );
+    }
+
+    #[test]
+    fn proof_covers_only_its_keys() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let key_a = [0u8; 32];
+        let key_b = [1u8; 32];
+        // This key shares its first byte with key_a, but it is not in the trie
+        let mut key_c = [0u8; 32];
+        key_c[1] = 1;
+        for key in [key_a, key_b] {
+            trie.insert(key, key);
+        }
+
+        let proof = prover::create_verkle_proof(&trie.storage, vec![key_a, key_b]);
+        assert!(proof.covers_keys(&[key_a, key_b]));
+
+        // Too few keys
+        assert!(!proof.covers_keys(&[key_a]));
+        // The same number of keys, but key_c's path passes through key_a's stem
+        assert!(!proof.covers_keys(&[key_a, key_c]));
+    }
+
+    #[test]
+    fn absence_proof_for_empty_slot_below_root() {
+        use ark_ff::Zero;
+        use bandersnatch::Fr;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // key_a and key_b share their first byte, so the root has a branch at index 0
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[1] = 1;
+        for key in [key_a, key_b] {
+            trie.insert(key, key);
+        }
+
+        // The path of this key ends at the empty slot 5 of the branch at depth 1
+        let mut absent_key = [0u8; 32];
+        absent_key[1] = 5;
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        let proof = prover::create_verkle_proof(&trie.storage, vec![absent_key]);
+        assert_eq!(proof.verification_hint.depths, vec![2]);
+        assert_eq!(
+            proof.verification_hint.extension_present,
+            vec![crate::proof::ExtPresent::None]
+        );
+
+        let (queries, _) =
+            verifier::create_verifier_queries(proof.clone(), vec![absent_key], vec![None], root)
+                .unwrap();
+        // The root is opened at 0 and the branch at [0] is opened at the empty slot 5, which is zero
+        let branch = trie.storage.get_branch_meta(&[0]).unwrap().commitment;
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[1].commitment, branch);
+        assert_eq!(queries[1].point, Fr::from(5u64));
+        assert_eq!(queries[1].result, Fr::zero());
+
+        let (ok, _) = proof.check(vec![absent_key], vec![None], root);
+        assert!(ok);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot create a proof with no keys")]
+    fn prover_rejects_empty_keys() {
+        let db = MemoryDb::new();
+        let trie = Trie::new(db, BasicCommitter);
+        prover::create_verkle_proof(&trie.storage, Vec::new());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn bincode_proof_roundtrip() {
+        use crate::proof::VerkleProof;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let mut keys = Vec::new();
+        for i in 0..=3 {
+            let mut key_0 = [0u8; 32];
+            key_0[0] = i;
+            keys.push(key_0);
+            trie.insert(key_0, key_0);
+        }
+        let root = vec![];
+        let meta = trie.storage.get_branch_meta(&root).unwrap();
+
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
+        let bytes = bincode::serialize(&proof).unwrap();
+        let got_proof: VerkleProof = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(got_proof.comms_sorted, proof.comms_sorted);
+
+        let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
+        let (ok, _) = got_proof.check(keys, values, meta.commitment);
+        assert!(ok);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_proof_batch_roundtrip() {
+        use crate::proof::VerkleProofBatch;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let keys: Vec<_> = (0..4u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        // A batch carries each proof with its verification hint, so it is a snapshot
+        // of everything that a stateless verifier needs besides the root
+        let mut batch = VerkleProofBatch::new();
+        batch.push(prover::create_verkle_proof(
+            &trie.storage,
+            keys[0..2].to_vec(),
+        ));
+        batch.push(prover::create_verkle_proof(
+            &trie.storage,
+            keys[2..4].to_vec(),
+        ));
+
+        let json = serde_json::to_string(&batch).unwrap();
+        let got_batch: VerkleProofBatch = serde_json::from_str(&json).unwrap();
+        assert_eq!(got_batch.len(), 2);
+        // Serialising again gives the same JSON, so nothing was lost in the round trip
+        assert_eq!(serde_json::to_string(&got_batch).unwrap(), json);
+
+        let proven_keys = vec![keys[0..2].to_vec(), keys[2..4].to_vec()];
+        let values = proven_keys
+            .iter()
+            .map(|keys| keys.iter().map(|key| Some(*key)).collect())
+            .collect();
+        assert!(got_batch.verify(proven_keys, values, root).is_ok());
+
+        // Commitments are encoded as their compressed bytes, which must still be valid points
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["proofs"][0]["comms_sorted"][0] = serde_json::json!(vec![255u8; 32]);
+        assert!(serde_json::from_value::<VerkleProofBatch>(value).is_err());
+    }
+
+    // Returns a compressed point which is on the curve, but not in the prime order subgroup
+    fn point_outside_subgroup() -> [u8; 32] {
+        use ark_serialize::CanonicalSerialize;
+        use bandersnatch::{EdwardsAffine, Fq};
+
+        let point = (1u64..)
+            .filter_map(|x| EdwardsAffine::get_point_from_x(Fq::from(x), true))
+            .find(|point| !point.is_in_correct_subgroup_assuming_on_curve())
+            .unwrap();
+        assert!(point.is_on_curve());
+
+        let mut bytes = [0u8; 32];
+        point.serialize(&mut bytes[..]).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn commitment_outside_subgroup_is_rejected() {
+        assert!(super::deserialize_commitment(&point_outside_subgroup()).is_err());
+    }
+
+    #[test]
+    fn compressed_root_outside_subgroup_is_rejected() {
+        use crate::proof::VerificationError;
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let keys = vec![[1u8; 32]];
+        trie.insert(keys[0], keys[0]);
+        let values = vec![Some(keys[0])];
+
+        let proof = trie.create_verkle_proof(keys.clone().into_iter());
+        assert_eq!(
+            proof
+                .verify_with_compressed_root(keys, values, point_outside_subgroup())
+                .err(),
+            Some(VerificationError::InvalidRoot)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn bincode_proof_rejects_points_outside_subgroup() {
+        use crate::proof::VerkleProof;
+        use ark_serialize::CanonicalSerialize;
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let keys = vec![[1u8; 32]];
+        trie.insert(keys[0], keys[0]);
+
+        let proof = trie.create_verkle_proof(keys.into_iter());
+        let mut bytes = bincode::serialize(&proof).unwrap();
+
+        // Replace the first commitment in the serialised proof
+        let mut comm_bytes = [0u8; 32];
+        proof.comms_sorted[0]
+            .serialize(&mut comm_bytes[..])
+            .unwrap();
+        let offset = bytes
+            .windows(32)
+            .position(|window| window == comm_bytes)
+            .unwrap();
+        bytes[offset..offset + 32].copy_from_slice(&point_outside_subgroup());
+
+        assert!(bincode::deserialize::<VerkleProof>(&bytes).is_err());
+    }
+
+    #[test]
+    fn duplicate_keys_are_proven_once() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let keys: Vec<_> = (0..3u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        let mut keys_with_duplicates = keys.clone();
+        keys_with_duplicates.extend_from_slice(&keys);
+        keys_with_duplicates.push(keys[1]);
+
+        let proof = trie.create_verkle_proof(keys.clone().into_iter());
+        let proof_with_duplicates = trie.create_verkle_proof(keys_with_duplicates.into_iter());
+
+        assert_eq!(proof_with_duplicates.comms_sorted, proof.comms_sorted);
+        let hint = &proof.verification_hint;
+        let hint_with_duplicates = &proof_with_duplicates.verification_hint;
+        assert_eq!(hint_with_duplicates.depths, hint.depths);
+        assert_eq!(
+            hint_with_duplicates.extension_present,
+            hint.extension_present
+        );
+        assert_eq!(
+            hint_with_duplicates.diff_stem_no_proof,
+            hint.diff_stem_no_proof
+        );
+
+        let values: Vec<_> = keys.iter().map(|key| Some(*key)).collect();
+        let (ok, _) = proof_with_duplicates.check(keys, values, root);
+        assert!(ok);
+    }
+
+    #[test]
+    fn proof_metrics() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // key_a and key_b share their first byte, so their stems are under the branch at [0]
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[1] = 1;
+        let key_c = [1u8; 32];
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+
+        let keys = vec![key_a, key_b, key_c, key_a];
+        let (proof, metrics) = trie.create_verkle_proof_with_metrics(keys.clone().into_iter());
+        assert_eq!(metrics.num_keys, 4);
+        // The root and the branch at [0]
+        assert_eq!(metrics.num_branches, 2);
+        assert_eq!(metrics.num_commitments, proof.num_commitments());
+
+        let values: Vec<_> = keys.iter().map(|key| Some(*key)).collect();
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        let (ok, _) = proof.check(keys, values, root);
+        assert!(ok);
+    }
+
+    #[test]
+    fn verify_batch_of_proofs() {
+        use crate::proof::{VerificationError, VerkleProofBatch};
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let keys: Vec<_> = (0..3u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        let mut batch = VerkleProofBatch::new();
+        for key in &keys {
+            batch.push(trie.create_verkle_proof(std::iter::once(*key)));
+        }
+        assert_eq!(batch.len(), 3);
+
+        let batch_keys: Vec<_> = keys.iter().map(|key| vec![*key]).collect();
+        let batch_values: Vec<_> = keys.iter().map(|key| vec![Some(*key)]).collect();
+        let result = batch
+            .clone()
+            .verify(batch_keys.clone(), batch_values.clone(), root);
+        assert_eq!(result.map(|update_hints| update_hints.len()).ok(), Some(3));
+
+        // Each proof is only checked against the keys at its own position
+        let mut wrong_keys = batch_keys.clone();
+        wrong_keys[0] = keys[0..2].to_vec();
+        let mut wrong_values = batch_values.clone();
+        wrong_values[0] = vec![Some(keys[0]), Some(keys[1])];
+        let result = batch.clone().verify(wrong_keys, wrong_values, root);
+        assert!(result.is_err());
+
+        let result = batch.verify(batch_keys[0..2].to_vec(), batch_values[0..2].to_vec(), root);
+        assert_eq!(
+            result.err(),
+            Some(VerificationError::BatchLengthMismatch {
+                num_proofs: 3,
+                num_claims: 2
+            })
+        );
+    }
+
+    // Pins the number of commitments in a proof and the number of queries the verifier must open,
+    // so that changes to the number of openings are noticed
+    #[test]
+    fn proof_commitment_and_verifier_query_count() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // Each key has its own stem at depth 1, and each value is in the stem's C_1 polynomial
+        let keys: Vec<_> = (0..4u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
+
+        // A commitment for each stem and its C_1
+        assert_eq!(proof.num_commitments(), 8);
+
+        // For each key, the verifier opens the root, the extension at 0, 1 and 2,
+        // and the low and high halves of the value in C_1
+        let values: Vec<_> = keys.iter().map(|key| Some(*key)).collect();
+        let (queries, _) = verifier::create_verifier_queries(proof, keys, values, root).unwrap();
+        assert_eq!(queries.len(), 24);
+    }
+
+    // Pins the serialised size of a proof, so that changes to the proof format are noticed
+    #[cfg(feature = "serde")]
+    #[test]
+    fn proof_serialised_size() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let keys: Vec<_> = (0..4u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+
+        let proof = prover::create_verkle_proof(&trie.storage, keys);
+
+        // bincode prefixes each list with an 8 byte length, and encodes each enum variant in 4 bytes:
+        // depths (8 + 4) + extension statuses (8 + 4 * 4) + other stems (8)
+        // + commitments for each stem and its C_1 (8 + 8 * 32)
+        let bytes = bincode::serialize(&proof).unwrap();
+        assert_eq!(bytes.len(), 308);
+    }
+
+    #[test]
+    fn prove_then_stateless_verify() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let claimed: Vec<_> = (0..4u8).map(|i| ([i; 32], [i + 1; 32])).collect();
+        for (key, value) in &claimed {
+            trie.insert(*key, *value);
+        }
+        let proof = trie.create_verkle_proof(claimed.iter().map(|(key, _)| *key));
+
+        // The verifier only has the root commitment, the proof and the claims
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        assert!(super::verify_proof(root, proof, &claimed));
+    }
+
+    #[test]
+    fn proof_from_read_only_storage() {
+        use crate::database::{BranchChild, BranchMeta, StemMeta};
+
+        // A snapshot which can only be read from, it does not implement `WriteOnlyHigherDb`
+        struct ReadOnlySnapshot<'a>(&'a MemoryDb);
+        impl ReadOnlyHigherDb for ReadOnlySnapshot<'_> {
+            fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+                self.0.get_stem_meta(stem_key)
+            }
+            fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+                self.0.get_branch_meta(key)
+            }
+            fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+                self.0.get_branch_children(branch_id)
+            }
+            fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+                self.0.get_branch_child(branch_id, index)
+            }
+            fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+                self.0.get_stem_children(stem_key)
+            }
+            fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+                self.0.get_leaf(key)
+            }
+        }
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+        let keys: Vec<_> = (0..4u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+
+        let snapshot = ReadOnlySnapshot(&trie.storage);
+        let root = snapshot.get_branch_meta(&[]).unwrap().commitment;
+        let proof = prover::create_verkle_proof(&snapshot, keys.clone());
+
+        let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
+        let (ok, _) = proof.check(keys, values, root);
+        assert!(ok);
+    }
+
     #[test]
     fn prover_queries_match_verifier_queries() {
         let db = MemoryDb::new();
@@ -138,7 +979,11 @@ mod test {
         let root = vec![];
         let meta = trie.storage.get_branch_meta(&root).unwrap();
 
-        let (pq, _) = prover::create_prover_queries(&trie.storage, keys.clone());
+        let (pq, _) = prover::create_prover_queries(
+            &trie.storage,
+            keys.clone(),
+            &mut super::BranchPolynomialCache::new(),
+        );
         let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
 
         let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
@@ -151,4 +996,63 @@ mod test {
             assert_eq!(p.result, v.result);
         }
     }
+
+    #[test]
+    fn cached_branch_polynomials_match_fresh_ones() {
+        use crate::proof::BranchPolynomialCache;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // The keys share their first byte, so every proof opens the root and the branch at [0]
+        let keys: Vec<_> = (0..4u8)
+            .map(|i| {
+                let mut key = [0u8; 32];
+                key[1] = i;
+                key
+            })
+            .collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+
+        let mut cache = BranchPolynomialCache::new();
+        let assert_cached_matches_fresh =
+            |trie: &Trie<MemoryDb, BasicCommitter>,
+             keys: Vec<[u8; 32]>,
+             cache: &mut BranchPolynomialCache| {
+                let (cached_queries, _) =
+                    prover::create_prover_queries(&trie.storage, keys.clone(), cache);
+                let (queries, _) = prover::create_prover_queries(
+                    &trie.storage,
+                    keys.clone(),
+                    &mut BranchPolynomialCache::new(),
+                );
+                assert_eq!(cached_queries.len(), queries.len());
+                for (cached, fresh) in cached_queries.into_iter().zip(queries) {
+                    assert_eq!(cached.commitment, fresh.commitment);
+                    assert_eq!(cached.result, fresh.result);
+                    assert_eq!(cached.polynomial, fresh.polynomial);
+                }
+
+                let cached_proof =
+                    prover::create_verkle_proof_with_cache(&trie.storage, keys.clone(), cache);
+                let proof = prover::create_verkle_proof(&trie.storage, keys);
+                assert_eq!(cached_proof.comms_sorted, proof.comms_sorted);
+                assert_eq!(
+                    cached_proof.verification_hint.depths,
+                    proof.verification_hint.depths
+                );
+            };
+
+        assert_cached_matches_fresh(&trie, keys[0..2].to_vec(), &mut cache);
+        assert_eq!(cache.len(), 2);
+        // The root and the branch at [0] are reused
+        assert_cached_matches_fresh(&trie, keys[1..4].to_vec(), &mut cache);
+        assert_eq!(cache.len(), 2);
+
+        // Updating a key changes the branches on its path, so the cached polynomials are stale
+        trie.insert(keys[2], [9u8; 32]);
+        assert_cached_matches_fresh(&trie, keys[1..4].to_vec(), &mut cache);
+    }
 }
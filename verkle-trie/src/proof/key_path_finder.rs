@@ -1,5 +1,4 @@
 use crate::database::{Meta, ReadOnlyHigherDb};
-use crate::trie::BranchId;
 
 // PathFinder is an algorithm to find the path to a given key
 // If the key is not found, this algorithm returns the path to the node where
@@ -44,7 +43,7 @@ pub(crate) enum KeyNotFound {
 }
 // The path to the key, including all of the nodes along the path
 pub(crate) struct KeyPath {
-    pub nodes: Vec<(BranchId, u8, Meta)>,
+    pub nodes: Vec<(Vec<u8>, u8, Meta)>,
     // depth refers to the depth that the key_path terminated at
     // This can also be computed by taking the length of the `nodes`
     pub depth: u8,
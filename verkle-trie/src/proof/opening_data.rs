@@ -5,9 +5,9 @@ use crate::{
     two_pow_128,
 };
 use ark_ff::{One, PrimeField, Zero};
-use bandersnatch::Fr;
+use bandersnatch::{EdwardsProjective, Fr};
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, HashMap},
     convert::TryInto,
 };
 
@@ -20,6 +20,8 @@ pub(crate) struct OpeningData {
     // Auxillary data that we collect while fetching the opening data
     pub(crate) extension_present_by_stem: BTreeMap<[u8; 31], ExtPresent>,
     pub(crate) depths_by_stem: BTreeMap<[u8; 31], u8>,
+    // The value of each key, or None if it is not in the trie
+    pub(crate) values_by_key: BTreeMap<[u8; 32], Option<[u8; 32]>>,
 }
 
 impl OpeningData {
@@ -105,6 +107,7 @@ impl OpeningData {
             let node_path = key_path.nodes;
             let key_state = key_path.key_state;
             let value = key_state.value();
+            opening_data.values_by_key.insert(key, value);
 
             let stem: [u8; 31] = key[0..31].try_into().unwrap();
             let suffix = key[31];
@@ -343,12 +346,13 @@ impl BranchOpeningData {
         &self,
         branch_path: &[u8],
         storage: &Storage,
+        cache: &mut BranchPolynomialCache,
     ) -> Vec<ProverQuery> {
         let mut branch_queries = Vec::with_capacity(self.children.len());
         let branch_meta = self.meta.into_branch();
 
         // Get all children hashes for this branch, return zero if the child is missing
-        let polynomial = get_branch_children_hashes(branch_path.to_vec(), storage);
+        let polynomial = cache.get_or_compute(branch_path, branch_meta.commitment, storage);
 
         // Create queries for all of the children we need
         for child_index in &self.children {
@@ -367,6 +371,53 @@ impl BranchOpeningData {
         branch_queries
     }
 }
+// Caches the polynomials for branch nodes, keyed by the path to the branch,
+// so that proofs over overlapping subtrees do not refetch all of a branch's children.
+//
+// Each polynomial is stored along with the commitment of the branch it was computed for,
+// so the polynomial for a branch which has been updated since is recomputed
+#[derive(Debug, Default)]
+pub struct BranchPolynomialCache {
+    polynomials: HashMap<Vec<u8>, (EdwardsProjective, Vec<Fr>)>,
+}
+
+impl BranchPolynomialCache {
+    pub fn new() -> BranchPolynomialCache {
+        BranchPolynomialCache::default()
+    }
+
+    // The number of branch polynomials in the cache
+    pub fn len(&self) -> usize {
+        self.polynomials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polynomials.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.polynomials.clear()
+    }
+
+    fn get_or_compute<Storage: ReadOnlyHigherDb>(
+        &mut self,
+        path: &[u8],
+        commitment: EdwardsProjective,
+        storage: &Storage,
+    ) -> Vec<Fr> {
+        if let Some((cached_commitment, polynomial)) = self.polynomials.get(path) {
+            if *cached_commitment == commitment {
+                return polynomial.clone();
+            }
+        }
+
+        let polynomial = get_branch_children_hashes(path.to_vec(), storage);
+        self.polynomials
+            .insert(path.to_vec(), (commitment, polynomial.clone()));
+        polynomial
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum Openings {
     Suffix(SuffixOpeningData),
@@ -1,19 +1,61 @@
 use super::{ProverQuery, VerificationHint, VerkleProof};
 use crate::{
     database::ReadOnlyHigherDb,
-    proof::opening_data::{OpeningData, Openings},
+    proof::opening_data::{BranchPolynomialCache, OpeningData, Openings},
 };
+use bandersnatch::EdwardsProjective;
 use itertools::Itertools;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 pub fn create_verkle_proof<Storage: ReadOnlyHigherDb>(
     storage: &Storage,
     keys: Vec<[u8; 32]>,
 ) -> VerkleProof {
-    assert!(keys.len() > 0, "cannot create a proof with no keys");
+    create_verkle_proof_with_cache(storage, keys, &mut BranchPolynomialCache::new())
+}
+
+// Same as `create_verkle_proof`, however the branch polynomials are taken from the cache
+// when possible, and the polynomials which are computed are added to it
+pub fn create_verkle_proof_with_cache<Storage: ReadOnlyHigherDb>(
+    storage: &Storage,
+    keys: Vec<[u8; 32]>,
+    cache: &mut BranchPolynomialCache,
+) -> VerkleProof {
+    create_verkle_proof_and_root(storage, keys, cache).1
+}
 
-    let (queries, verification_hint) = create_prover_queries(storage, keys);
+// Same as `create_verkle_proof_with_cache`, however the root commitment that the proof was
+// created against is also returned. This is the root which was read while creating the proof,
+// so it is not affected by writes to the storage before or after
+pub fn create_verkle_proof_and_root<Storage: ReadOnlyHigherDb>(
+    storage: &Storage,
+    keys: Vec<[u8; 32]>,
+    cache: &mut BranchPolynomialCache,
+) -> (EdwardsProjective, VerkleProof) {
+    let (queries, verification_hint) = create_prover_queries(storage, keys, cache);
+    proof_from_queries(queries, verification_hint)
+}
+
+// Same as `create_verkle_proof_with_cache`, however the value of each key is also returned.
+// The values are the ones which were read while finding the path to each key for the proof,
+// so the keys are not looked up a second time
+pub(crate) fn create_verkle_proof_and_values<Storage: ReadOnlyHigherDb>(
+    storage: &Storage,
+    keys: Vec<[u8; 32]>,
+    cache: &mut BranchPolynomialCache,
+) -> (BTreeMap<[u8; 32], Option<[u8; 32]>>, VerkleProof) {
+    let mut opening_data = collect_opening_data(storage, keys);
+    let values = std::mem::take(&mut opening_data.values_by_key);
 
+    let (queries, verification_hint) = open_queries(storage, opening_data, cache);
+    let (_, proof) = proof_from_queries(queries, verification_hint);
+    (values, proof)
+}
+
+fn proof_from_queries(
+    queries: Vec<ProverQuery>,
+    verification_hint: VerificationHint,
+) -> (EdwardsProjective, VerkleProof) {
     // Commitments without duplicates and without the root, (implicitly) sorted by path, since the queries were
     // processed by path order
     let root_comm = queries
@@ -33,10 +75,11 @@ pub fn create_verkle_proof<Storage: ReadOnlyHigherDb>(
 
     // TODO create proof over queries when IPA is added
 
-    VerkleProof {
+    let proof = VerkleProof {
         comms_sorted,
         verification_hint,
-    }
+    };
+    (root_comm, proof)
 }
 
 // First we need to produce all of the key paths for a key
@@ -48,10 +91,30 @@ pub fn create_verkle_proof<Storage: ReadOnlyHigherDb>(
 pub(super) fn create_prover_queries<Storage: ReadOnlyHigherDb>(
     storage: &Storage,
     keys: Vec<[u8; 32]>,
+    cache: &mut BranchPolynomialCache,
 ) -> (Vec<ProverQuery>, VerificationHint) {
+    let opening_data = collect_opening_data(storage, keys);
+    open_queries(storage, opening_data, cache)
+}
+
+fn collect_opening_data<Storage: ReadOnlyHigherDb>(
+    storage: &Storage,
+    keys: Vec<[u8; 32]>,
+) -> OpeningData {
     assert!(keys.len() > 0, "cannot create a proof with no keys");
 
-    let opening_data = OpeningData::collect_opening_data(keys, storage);
+    // A key which is given more than once is only proven once, so that the same
+    // polynomial is not opened at the same point multiple times
+    let keys: Vec<_> = keys.into_iter().unique().collect();
+
+    OpeningData::collect_opening_data(keys, storage)
+}
+
+fn open_queries<Storage: ReadOnlyHigherDb>(
+    storage: &Storage,
+    opening_data: OpeningData,
+    cache: &mut BranchPolynomialCache,
+) -> (Vec<ProverQuery>, VerificationHint) {
     let openings = opening_data.openings;
     let extension_present_by_stem = opening_data.extension_present_by_stem;
     let depths_by_stem = opening_data.depths_by_stem;
@@ -66,7 +129,7 @@ pub(super) fn create_prover_queries<Storage: ReadOnlyHigherDb>(
     for (path, openings) in &openings {
         match openings {
             Openings::Suffix(so) => queries.extend(so.open_query(storage)),
-            Openings::Branch(bo) => queries.extend(bo.open_query(path, storage)),
+            Openings::Branch(bo) => queries.extend(bo.open_query(path, storage, cache)),
             Openings::Extension(eo) => {
                 diff_stem_no_proof.insert(eo.stem);
                 queries.extend(eo.open_query(false, false));
@@ -1,7 +1,7 @@
 use crate::{
+    byte_arr::Value,
     group_to_field,
-    proof::{ExtPresent, UpdateHint, VerifierQuery},
-    two_pow_128,
+    proof::{ExtPresent, UpdateHint, VerificationError, VerifierQuery},
 };
 use ark_ff::{One, PrimeField, Zero};
 use bandersnatch::{EdwardsProjective, Fr};
@@ -11,13 +11,42 @@ use std::{
 };
 
 use super::VerkleProof;
+use crate::MAX_DEPTH;
+
 // TODO Document this better and refactor
 pub fn create_verifier_queries(
     proof: VerkleProof,
     keys: Vec<[u8; 32]>,
     values: Vec<Option<[u8; 32]>>,
     root: EdwardsProjective,
-) -> Option<(Vec<VerifierQuery>, UpdateHint)> {
+) -> Result<(Vec<VerifierQuery>, UpdateHint), VerificationError> {
+    if keys.len() != values.len() {
+        return Err(VerificationError::KeyValueLengthMismatch {
+            num_keys: keys.len(),
+            num_values: values.len(),
+        });
+    }
+
+    // The prover refuses to create a proof for an empty set of keys.
+    // An empty set of keys is trivially valid, if the proof is also empty,
+    // so we return no queries instead of reconstructing paths for nothing.
+    if keys.is_empty() {
+        let proof_is_empty = proof.comms_sorted.is_empty()
+            && proof.verification_hint.depths.is_empty()
+            && proof.verification_hint.extension_present.is_empty()
+            && proof.verification_hint.diff_stem_no_proof.is_empty();
+        if !proof_is_empty {
+            return Err(VerificationError::NonEmptyProofForNoKeys);
+        }
+
+        let update_hint = UpdateHint {
+            depths_and_ext_by_stem: BTreeMap::new(),
+            commitments_by_path: BTreeMap::new(),
+            other_stems_by_prefix: BTreeMap::new(),
+        };
+        return Ok((Vec::new(), update_hint));
+    }
+
     let commitments_sorted_by_path: Vec<_> =
         std::iter::once(root).chain(proof.comms_sorted).collect();
 
@@ -27,6 +56,25 @@ pub fn create_verifier_queries(
         .map(|key| key[0..31].try_into().unwrap())
         .collect();
 
+    // The prover creates one depth and extension status for each stem
+    let num_depths = proof.verification_hint.depths.len();
+    let num_extension_present = proof.verification_hint.extension_present.len();
+    if num_depths != stems.len() || num_extension_present != stems.len() {
+        return Err(VerificationError::HintLengthMismatch {
+            num_stems: stems.len(),
+            num_depths,
+            num_extension_present,
+        });
+    }
+    if let Some(depth) = proof
+        .verification_hint
+        .depths
+        .iter()
+        .find(|depth| **depth == 0 || **depth > MAX_DEPTH)
+    {
+        return Err(VerificationError::DepthOutOfRange { depth: *depth });
+    }
+
     let mut depths_and_ext_by_stem: BTreeMap<[u8; 31], (ExtPresent, u8)> = BTreeMap::new();
 
     let mut stems_with_extension: BTreeSet<[u8; 31]> = BTreeSet::new();
@@ -92,11 +140,7 @@ pub fn create_verifier_queries(
                 all_paths_and_zs.insert((suffix_tree_path.clone(), val_upper_index));
 
                 let (value_low, value_high) = match value {
-                    Some(val) => {
-                        let value_low = Fr::from_le_bytes_mod_order(&val[0..16]) + two_pow_128();
-                        let value_high = Fr::from_le_bytes_mod_order(&val[16..32]);
-                        (value_low, value_high)
-                    }
+                    Some(val) => Value::from_arr(val).to_field_pair(),
                     None => (Fr::zero(), Fr::zero()),
                 };
                 leaf_values_by_path_and_z
@@ -107,7 +151,7 @@ pub fn create_verifier_queries(
                 // Since this stem points to a different stem,
                 // the value was never set
                 if value.is_some() {
-                    return None;
+                    return Err(VerificationError::ValueForAbsentKey { key });
                 }
 
                 // Check if this stem already has an extension proof
@@ -120,7 +164,9 @@ pub fn create_verifier_queries(
                 // depth cannot be 31 because then that would mean that stem[...depth]
                 // is looking for it's tem. This is not possible, because we have already
                 // noted that ExtPresent is DifferentStem
-                assert!(depth != stem.len() as u8);
+                if depth == MAX_DEPTH {
+                    return Err(VerificationError::DepthOutOfRange { depth });
+                }
 
                 let mut other_stem = None;
                 let mut found: Vec<_> = stems_with_extension
@@ -128,8 +174,7 @@ pub fn create_verifier_queries(
                     .filter(|x| x[0..depth as usize] == stem[0..depth as usize])
                     .collect();
                 if found.len() > 1 {
-                    // TODO return error instead when we change the func signature to return Result instead of bool
-                    panic!("found more than one instance of stem_with_extension at depth {}, see: {:?}", depth, found)
+                    return Err(VerificationError::AmbiguousOtherStem { key, depth });
                 } else if found.len() == 1 {
                     other_stem = found.pop();
                 }
@@ -141,9 +186,10 @@ pub fn create_verifier_queries(
                         .iter()
                         .filter(|x| x[0..depth as usize] == stem[0..depth as usize])
                         .collect();
-                    let encountered_stem = found.pop().expect(
-                        "ExtPresent::DifferentStem flag but we cannot find the encountered stem",
-                    );
+                    let encountered_stem = match found.pop() {
+                        Some(encountered_stem) => encountered_stem,
+                        None => return Err(VerificationError::MissingOtherStem { key, depth }),
+                    };
                     other_stem = Some(encountered_stem);
 
                     other_stems_used.insert(*encountered_stem);
@@ -161,18 +207,30 @@ pub fn create_verifier_queries(
         } else if extpres == ExtPresent::None {
             // If the extension  was not present, then the value should be None
             if value.is_some() {
-                return None;
+                return Err(VerificationError::ValueForAbsentKey { key });
             }
 
+            // The empty slot is the child at stem[depth - 1] of the last branch node on the path
             leaf_values_by_path_and_z.insert(
-                (stem[0..depth as usize].to_vec(), stem[depth as usize - 1]),
+                (
+                    stem[0..depth as usize - 1].to_vec(),
+                    stem[depth as usize - 1],
+                ),
                 Fr::zero(),
             );
         }
     }
 
-    assert!(proof.verification_hint.diff_stem_no_proof == other_stems_used);
-    assert!(commitments_sorted_by_path.len() == all_paths.len());
+    if proof.verification_hint.diff_stem_no_proof != other_stems_used {
+        return Err(VerificationError::UnusedOtherStems);
+    }
+    // There is one commitment for each node that is opened, including the root
+    if commitments_sorted_by_path.len() != all_paths.len() {
+        return Err(VerificationError::CommitmentCountMismatch {
+            expected: all_paths.len(),
+            got: commitments_sorted_by_path.len(),
+        });
+    }
 
     let commitments_by_path: BTreeMap<Vec<_>, EdwardsProjective> = all_paths
         .into_iter()
@@ -221,5 +279,5 @@ pub fn create_verifier_queries(
         other_stems_by_prefix,
     };
 
-    Some((queries, update_hint))
+    Ok((queries, update_hint))
 }
@@ -0,0 +1,73 @@
+use crate::{Committer, SRS};
+use ark_ff::Zero;
+use bandersnatch::{EdwardsProjective, Fr};
+
+// The transport used to send evaluations to the service which holds the SRS,
+// and to receive the commitment to those evaluations
+pub type CommitTransport = Box<dyn Fn(&[Fr]) -> EdwardsProjective + Send + Sync>;
+
+// A committer which does not hold the SRS, instead it forwards all commitments
+// to a separate service, through the transport.
+pub struct RemoteCommitter {
+    transport: CommitTransport,
+}
+
+impl RemoteCommitter {
+    pub fn new(transport: CommitTransport) -> RemoteCommitter {
+        RemoteCommitter { transport }
+    }
+}
+
+impl std::fmt::Debug for RemoteCommitter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RemoteCommitter").finish_non_exhaustive()
+    }
+}
+
+impl Committer for RemoteCommitter {
+    fn commit_lagrange(&self, evaluations: &[Fr]) -> EdwardsProjective {
+        (self.transport)(evaluations)
+    }
+
+    // The service only commits to evaluations, so value * G_i is the commitment
+    // to the evaluations which are zero everywhere except at `lagrange_index`.
+    // This makes a request for every delta update, so it is slower than a local committer
+    fn scalar_mul(&self, value: Fr, lagrange_index: usize) -> EdwardsProjective {
+        if value.is_zero() {
+            return self.identity_commitment();
+        }
+        let mut evaluations = vec![Fr::zero(); SRS.len()];
+        evaluations[lagrange_index] = value;
+        self.commit_lagrange(&evaluations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RemoteCommitter;
+    use crate::database::memory_db::MemoryDb;
+    use crate::{trie::Trie, BasicCommitter, Committer};
+    use bandersnatch::Fr;
+
+    #[test]
+    fn remote_committer_matches_local_committer() {
+        // The "remote" service runs in process, and uses the basic committer
+        let remote = RemoteCommitter::new(Box::new(|evaluations: &[Fr]| {
+            BasicCommitter.commit_lagrange(evaluations)
+        }));
+
+        let value = Fr::from(123u64);
+        assert_eq!(
+            remote.scalar_mul(value, 7),
+            BasicCommitter.scalar_mul(value, 7)
+        );
+
+        let mut remote_trie = Trie::new(MemoryDb::new(), remote);
+        let mut local_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        for i in 0..4u8 {
+            remote_trie.insert([i; 32], [i + 1; 32]);
+            local_trie.insert([i; 32], [i + 1; 32]);
+        }
+        assert_eq!(remote_trie.compute_root(), local_trie.compute_root());
+    }
+}
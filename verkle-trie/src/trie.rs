@@ -1,21 +1,67 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryInto;
 
-use crate::database::{BranchMeta, Flush, Meta, ReadWriteHigherDb, StemMeta};
-use crate::{byte_arr::Key, group_to_field, SRS};
-use crate::{two_pow_128, Committer};
+use crate::database::memory_db::MemoryDb;
+use crate::database::{
+    BranchChild, BranchMeta, DbError, Flush, FlushChecksum, Meta, ReadOnlyHigherDb,
+    ReadWriteHigherDb, StemMeta, VerkleDb,
+};
+use crate::VerkleError;
+use crate::{byte_arr, byte_arr::Key, group_to_field, identity_hash, BasicCommitter};
+use crate::{two_pow_128, Committer, SmallVec32, MAX_DEPTH};
+use ark_ec::AffineCurve;
 use ark_ff::{PrimeField, Zero};
 use ark_serialize::CanonicalSerialize;
 use bandersnatch::{EdwardsProjective, Fr};
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+use verkle_db::BareMetalKVDb;
+
+mod audit;
+mod dot;
+mod iter;
+pub use audit::AuditError;
+pub use iter::Leaves;
 
 #[derive(Debug, Clone)]
 // The trie implements the logic to insert values, fetch values, and create paths to said values
 pub struct Trie<Storage, PolyCommit: Committer> {
     pub(crate) storage: Storage,
     committer: PolyCommit,
+    // When enabled, the root is recorded after each flush, keyed by block number.
+    // The second item is the block number that the next flush will be recorded under.
+    root_history: Option<(BTreeMap<u64, Fr>, u64)>,
+    // The stem and depth of the last key updated with `update_hot_key`.
+    // This is cleared whenever a chain insert could have moved the stem deeper into the trie
+    hot_stem: Option<([u8; 31], u8)>,
+    // Called with the path and new commitment of each branch node whose commitment is updated
+    on_branch_updated: Option<BranchUpdateHook>,
+    // The maximum number of distinct keys that a single proof can be created for, if any
+    max_proof_keys: Option<usize>,
+    // The root returned by the last call to `compute_root`.
+    // This is cleared whenever the trie is modified.
+    // A `OnceCell` from once_cell::sync is used, so that the trie can still be shared between threads
+    cached_root: OnceCell<Fr>,
+    // The number of keys in the trie. It starts from the count in the checksum of the last flush,
+    // and is stored with the root in the next one
+    num_keys: u64,
+}
+
+// A callback which is notified of branch commitment updates, so that an external cache
+// can mirror the commitments in the trie
+type BranchUpdateFn = dyn Fn(&[u8], EdwardsProjective) + Send + Sync;
+#[derive(Clone)]
+struct BranchUpdateHook(std::sync::Arc<BranchUpdateFn>);
+
+impl std::fmt::Debug for BranchUpdateHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BranchUpdateHook")
+    }
 }
 
-// To identify a branch, we only need to provide the path to the branch
-pub(crate) type BranchId = Vec<u8>;
+// To identify a branch, we only need to provide the path to the branch.
+// A path is at most 31 bytes, so it never allocates
+pub(crate) type BranchId = SmallVec32;
 
 // Modifying the Trie is done by creating Instructions and
 // then executing them. The trie can only be modified via the
@@ -55,7 +101,7 @@ enum Ins {
     // existing key
     ChainInsert {
         starting_depth: u8,
-        chain_insert_path: Vec<u8>,
+        chain_insert_path: SmallVec32,
         parent_branch_node: BranchId,
         // This is the index of the child which currently has a stem node,
         // but wil become a branch node
@@ -88,6 +134,16 @@ enum Ins {
     },
 }
 
+// The instructions to insert a value, created by `Trie::prepare` and applied by `Trie::commit`
+#[derive(Debug)]
+pub struct PreparedInsert {
+    key: [u8; 32],
+    value: [u8; 32],
+    // The root of the trie that the instructions were created for
+    root: Fr,
+    instructions: Vec<Ins>,
+}
+
 impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
     // Creates a new Trie object
     pub fn new(mut db: Storage, pc: PolyCommit) -> Self {
@@ -102,15 +158,243 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
             let old_val = db.insert_branch(vec![], BranchMeta::zero(), 0);
             assert!(old_val.is_none());
         }
+        let num_keys = db
+            .get_flush_checksum()
+            .map_or(0, |checksum| checksum.num_keys);
         Trie {
             storage: db,
             committer: pc,
+            root_history: None,
+            hot_stem: None,
+            on_branch_updated: None,
+            max_proof_keys: None,
+            cached_root: OnceCell::new(),
+            num_keys,
         }
     }
 
-    pub fn insert(&mut self, key_bytes: [u8; 32], value_bytes: [u8; 32]) {
+    // Creates a new Trie object and inserts all of the items into it.
+    //
+    // The items are sorted by key and inserted one stem at a time with `insert_stem_values`,
+    // so each stem and the branch nodes above it are updated once per stem.
+    // If a key appears more than once, only its last value is inserted
+    pub fn new_from_iter(
+        db: Storage,
+        pc: PolyCommit,
+        items: impl IntoIterator<Item = ([u8; 32], [u8; 32])>,
+    ) -> Self {
+        let items: BTreeMap<[u8; 32], [u8; 32]> = items.into_iter().collect();
+
+        let mut trie = Trie::new(db, pc);
+        trie.insert_sorted_by_stem(items);
+        trie
+    }
+
+    // Inserts the value at the key and returns true if the trie changed.
+    // The trie does not change if the key already holds this value
+    pub fn insert(&mut self, key_bytes: [u8; 32], value_bytes: [u8; 32]) -> bool {
         let ins = self.create_insert_instructions(key_bytes, value_bytes);
+        // No instructions are created when the value is already stored at the key
+        let changed = !ins.is_empty();
         self.process_instructions(ins);
+        changed
+    }
+
+    // Stores a zero value at the key. Unlike a key which was never inserted, the key is in the trie,
+    // and the stem commits to it, since the low 16 bytes of every stored value have 2^128 added to them
+    pub fn insert_empty(&mut self, key_bytes: [u8; 32]) -> bool {
+        self.insert(key_bytes, [0u8; 32])
+    }
+
+    // Inserts a value of any length and returns true if the trie changed.
+    //
+    // The leaf holds the hash of the length prefixed value, which is committed to like any other
+    // leaf value, and the value itself is stored in the database, keyed by the hash.
+    // Use `get_bytes` to read it back.
+    // An error is returned if the database cannot store the value, in which case the trie is unchanged
+    pub fn insert_bytes(&mut self, key_bytes: [u8; 32], value: &[u8]) -> Result<bool, VerkleError> {
+        let hash = hash_value_bytes(value);
+        if self.storage.get_preimage(hash).is_none() {
+            self.storage.insert_preimage(hash, value.to_vec())?;
+        }
+        Ok(self.insert(key_bytes, hash))
+    }
+
+    // Creates the instructions to insert the value at the key, without modifying the trie.
+    // This only reads from the trie, so many inserts can be prepared at the same time
+    pub fn prepare(&self, key_bytes: [u8; 32], value_bytes: [u8; 32]) -> PreparedInsert {
+        PreparedInsert {
+            key: key_bytes,
+            value: value_bytes,
+            root: self.compute_root(),
+            instructions: self.create_insert_instructions(key_bytes, value_bytes),
+        }
+    }
+
+    // Applies an insert created by `prepare` and returns true if the trie changed.
+    //
+    // The instructions hold values which were read when the insert was prepared, so they are only
+    // applied if the trie has not changed since. Otherwise they are recreated, which means that
+    // inserts prepared on the same trie can be committed one after the other
+    pub fn commit(&mut self, prepared: PreparedInsert) -> bool {
+        if prepared.root != self.compute_root() {
+            return self.insert(prepared.key, prepared.value);
+        }
+
+        let changed = !prepared.instructions.is_empty();
+        self.process_instructions(prepared.instructions);
+        changed
+    }
+
+    // Updates a key which is updated frequently, such as a counter.
+    //
+    // The path to a stem is determined by the stem and its depth, so we cache the depth of the
+    // stem for the last hot key and create the instructions directly, instead of walking the trie.
+    // Falls back to `insert` when the key's stem is not in the trie yet.
+    pub fn update_hot_key(&mut self, key_bytes: [u8; 32], value_bytes: [u8; 32]) {
+        let stem = Key::from_arr(key_bytes).stem();
+
+        let depth = match self.hot_stem {
+            Some((hot_stem, depth)) if hot_stem == stem => depth,
+            _ => match self.stem_depth(stem) {
+                Some(depth) => depth,
+                None => {
+                    self.insert(key_bytes, value_bytes);
+                    return;
+                }
+            },
+        };
+
+        let mut instructions = self.fall_through_instructions(stem, depth);
+        instructions.push(Ins::UpdateLeaf {
+            key: key_bytes,
+            new_leaf_value: value_bytes,
+            depth,
+            branch_id: BranchId::from_slice(&stem[0..depth as usize - 1]),
+            branch_child_index: stem[depth as usize - 1],
+        });
+
+        self.process_instructions(instructions);
+        self.hot_stem = Some((stem, depth));
+    }
+
+    // Inserts multiple values under the same stem and returns true if the trie changed.
+    //
+    // All of the leaves are stored before the stem is updated, so C_1, C_2, the stem commitment
+    // and the branch nodes above the stem are updated once, instead of once per value.
+    // If the stem is not in the trie yet, the first value is inserted on its own to add it
+    pub fn insert_stem_values(&mut self, stem: [u8; 31], values: &[(u8, [u8; 32])]) -> bool {
+        let key_with_suffix = |suffix: u8| {
+            let mut key = [0u8; 32];
+            key[0..31].copy_from_slice(&stem);
+            key[31] = suffix;
+            key
+        };
+
+        let (depth, values, mut changed) = match self.stem_depth(stem) {
+            Some(depth) => (depth, values, false),
+            None => {
+                let ((suffix, value), rest) = match values.split_first() {
+                    Some(first_and_rest) => first_and_rest,
+                    None => return false,
+                };
+                self.insert(key_with_suffix(*suffix), *value);
+                (self.stem_depth(stem).unwrap(), rest, true)
+            }
+        };
+
+        let leaf_updates: Vec<_> = values
+            .iter()
+            .filter_map(|(suffix, value)| {
+                self.update_leaf_table(key_with_suffix(*suffix), *value, depth)
+            })
+            .collect();
+        if leaf_updates.is_empty() {
+            return changed;
+        }
+        changed = true;
+        self.cached_root.take();
+
+        // The old values of the branch nodes on the path are needed to compute their deltas,
+        // so these instructions are created before the stem and its parent are updated
+        let instructions = self.fall_through_instructions(stem, depth);
+
+        let stem_update = self.update_stem_table_with_leaves(stem, leaf_updates, depth);
+        self.update_branch_table(
+            stem_update,
+            BranchId::from_slice(&stem[0..depth as usize - 1]),
+            stem[depth as usize - 1],
+            depth,
+        );
+        self.process_instructions(instructions);
+
+        changed
+    }
+
+    // Creates the instructions to update each branch node on the path to the stem,
+    // excluding the branch node which is the parent of the stem
+    fn fall_through_instructions(&self, stem: [u8; 31], depth: u8) -> Vec<Ins> {
+        let mut instructions = Vec::with_capacity(depth as usize);
+        for i in 0..(depth - 1) as usize {
+            let child = BranchId::from_slice(&stem[0..i + 1]);
+            let old_child_value = self.storage.get_branch_meta(&child).map(Meta::from);
+            instructions.push(Ins::InternalNodeFallThrough {
+                branch_id: BranchId::from_slice(&stem[0..i]),
+                branch_child_index: stem[i],
+                child,
+                old_child_value,
+                depth: i as u8 + 1,
+            });
+        }
+        instructions
+    }
+
+    // Applies a pre-computed state diff, where `Some(value)` is a write and `None` is a delete.
+    //
+    // If a key appears more than once, only its last entry is applied.
+    // Writes are grouped by stem and applied with `insert_stem_values`, so each stem
+    // and the branch nodes above it are updated once per stem instead of once per key.
+    //
+    // The trie cannot remove leaves yet, so deleting a key which is in the trie returns an error,
+    // and the diff is not applied. Deleting a key which is not in the trie is a no-op
+    pub fn apply_state_diff(
+        &mut self,
+        diff: &[([u8; 32], Option<[u8; 32]>)],
+    ) -> Result<(), VerkleError> {
+        let diff: BTreeMap<[u8; 32], Option<[u8; 32]>> = diff.iter().copied().collect();
+
+        // Deletes are checked before anything is written, so that a rejected diff leaves the trie unchanged
+        for (key, value) in &diff {
+            if value.is_none() && self.get(*key).is_some() {
+                return Err(VerkleError::DeleteNotSupported { key: *key });
+            }
+        }
+
+        let writes = diff
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)));
+        self.insert_sorted_by_stem(writes);
+        Ok(())
+    }
+
+    // Inserts items which are sorted by key, calling `insert_stem_values` once for each stem
+    fn insert_sorted_by_stem(&mut self, items: impl IntoIterator<Item = ([u8; 32], [u8; 32])>) {
+        let mut stem = None;
+        let mut stem_values = Vec::new();
+        for (key, value) in items {
+            let key_stem = Key::from_arr(key).stem();
+            if stem != Some(key_stem) {
+                if let Some(stem) = stem {
+                    self.insert_stem_values(stem, &stem_values);
+                }
+                stem = Some(key_stem);
+                stem_values.clear();
+            }
+            stem_values.push((key[31], value));
+        }
+        if let Some(stem) = stem {
+            self.insert_stem_values(stem, &stem_values);
+        }
     }
 
     // Inserting a leaf in the trie is done in two steps
@@ -126,7 +410,7 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
 
         let path_indices = key.path_indices();
 
-        let mut current_node_index = vec![];
+        let mut current_node_index = BranchId::new();
 
         // The loop index lets us know what level in the tree we are at
         for (loop_index, path_index) in path_indices.enumerate() {
@@ -185,31 +469,26 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
             // to represent this.
 
             let (shared_path, path_diff_old, path_diff_new) =
-                Key::path_difference(child.stem().unwrap(), key_bytes[0..31].try_into().unwrap());
+                Key::path_difference(child.stem().unwrap(), key.stem());
 
             // Case3a: Lets check if this key belongs under the stem
             if shared_path.len() == 31 {
                 // The new key and the old child belong under the same stem
 
+                // If there is no old value, then there are other values under this stem, but this is
+                // the first value under this entry. This is an insert, even if the new value is zero,
+                // since a stored zero is committed to differently from an empty entry
                 let leaf_val = self.storage.get_leaf(key_bytes);
-                let old_leaf_val = match leaf_val {
-                    Some(old_val) => {
-                        // There was an old value in the stem, so this is an update
-                        old_val
-                    }
-                    None => {
-                        // There are other values under this stem, but this is the first value under this entry
-                        [0u8; 32]
-                    }
-                };
 
                 // If the key is being updated to exactly the same value, we just return nothing
                 if path_diff_old.is_none() {
                     // This means that they share all 32 bytes
                     assert!(path_diff_new.is_none());
                     // We return nothing if the value is the same
-                    if old_leaf_val == value_bytes {
-                        return Vec::new();
+                    if let Some(old_leaf_val) = leaf_val {
+                        if byte_arr::ct_eq(&old_leaf_val, &value_bytes) {
+                            return Vec::new();
+                        }
                     }
                 }
 
@@ -236,7 +515,7 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
             let p_diff_new = path_diff_new.unwrap();
 
             instructions.push(Ins::ChainInsert {
-                chain_insert_path: relative_shared_path.to_vec(),
+                chain_insert_path: SmallVec32::from_slice(relative_shared_path),
                 starting_depth: loop_index as u8,
                 parent_branch_node: current_node_index,
                 child_index: path_index,
@@ -253,6 +532,9 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
     }
     // Process instructions in reverse order
     fn process_instructions(&mut self, instructions: Vec<Ins>) {
+        if !instructions.is_empty() {
+            self.cached_root.take();
+        }
         for ins in instructions.into_iter().rev() {
             match ins {
                 Ins::InternalNodeFallThrough {
@@ -283,7 +565,7 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
                     let updated_comm = old_branch_comm + delta_comm;
                     let hash_updated_comm = group_to_field(&updated_comm);
 
-                    self.storage.insert_branch(
+                    self.insert_branch_meta(
                         branch_id,
                         BranchMeta {
                             commitment: updated_comm,
@@ -329,9 +611,12 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
                 } => {
                     assert!(chain_insert_path.len() > 0);
 
+                    // The stem being moved down could be the hot stem, so its cached depth is no longer valid
+                    self.hot_stem = None;
+
                     //0. Compute the path for each inner node
                     let mut inner_node_paths =
-                        paths_from_relative(parent_branch_node.clone(), chain_insert_path.clone());
+                        paths_from_relative(&parent_branch_node, &chain_insert_path);
                     //
                     // 1. First check that before modification, the node which starts the chain is a stem
                     // we will later replace it later with an inner node.
@@ -346,9 +631,9 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
                     //2a. Now lets create the inner node which will hold the two stems
                     // Note; it's position will be at the bottom of the chain.
                     let bottom_inner_node_path = inner_node_paths.pop().unwrap();
-                    let bottom_inode_depth = bottom_inner_node_path.len() as u8;
+                    let bottom_inode_depth = depth_of_path(&bottom_inner_node_path);
                     self.storage.insert_branch(
-                        bottom_inner_node_path.clone(),
+                        bottom_inner_node_path.to_vec(),
                         BranchMeta::zero(),
                         bottom_inode_depth,
                     );
@@ -405,14 +690,14 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
                     for (child_path, parent_branch_node) in
                         chain_insert_path.iter().rev().zip(shortened_path)
                     {
-                        let depth = parent_branch_node.len() as u8;
+                        let depth = depth_of_path(parent_branch_node);
 
                         let delta = inner_node_below_val; // Remember the old value will be zero, since we just created it.
 
                         let updated_comm = self.committer.scalar_mul(delta, *child_path as usize);
                         let branch_root = group_to_field(&updated_comm);
 
-                        self.storage.insert_branch(
+                        self.insert_branch_meta(
                             parent_branch_node.clone(),
                             BranchMeta {
                                 commitment: updated_comm,
@@ -440,7 +725,7 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
                     let mut dbg_root = [0u8; 32];
                     top_parent_root.serialize(&mut dbg_root[..]).unwrap();
 
-                    self.storage.insert_branch(
+                    self.insert_branch_meta(
                         parent_branch_node.clone(),
                         BranchMeta {
                             commitment: updated_top_comm,
@@ -455,18 +740,146 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
     pub fn get(&self, key: [u8; 32]) -> Option<[u8; 32]> {
         self.storage.get_leaf(key)
     }
+
+    // Returns the value inserted with `insert_bytes`.
+    // Returns None if the key is missing or its value was inserted with `insert`
+    pub fn get_bytes(&self, key: [u8; 32]) -> Option<Vec<u8>> {
+        let hash = self.storage.get_leaf(key)?;
+        self.storage.get_preimage(hash)
+    }
+
+    // Same as `get`, however if the storage fails to read the key, the error is returned
+    // instead of panicking
+    pub fn try_get(&self, key: [u8; 32]) -> Result<Option<[u8; 32]>, DbError> {
+        self.storage.try_get_leaf(key)
+    }
+
+    // Returns whether the value stored at each key matches the claimed value.
+    // A claim for a key which is not in the trie does not match
+    pub fn bulk_verify_keys(&self, claims: &[([u8; 32], [u8; 32])]) -> Vec<bool> {
+        claims
+            .iter()
+            .map(|(key, value)| self.get(*key) == Some(*value))
+            .collect()
+    }
+
+    // Returns the value at the key along with the depth of its stem, see `stem_depth`
+    pub fn get_leaf_with_depth(&self, key: [u8; 32]) -> Option<([u8; 32], u8)> {
+        let value = self.get(key)?;
+        let depth = self
+            .stem_depth(Key::from_arr(key).stem())
+            .expect("leaf is in the trie, but its stem is not");
+        Some((value, depth))
+    }
+
+    // Returns the sum of the stem commitments for the stems of the given keys.
+    // Each stem is only counted once, even if several keys are under it,
+    // and keys whose stem is not in the trie do not contribute to the sum
+    pub fn commitment_over_keys(&self, keys: &[[u8; 32]]) -> EdwardsProjective {
+        let stems: BTreeSet<[u8; 31]> = keys.iter().map(|key| Key::from_arr(*key).stem()).collect();
+
+        stems
+            .into_iter()
+            .filter_map(|stem| self.storage.get_stem_meta(stem))
+            .map(|stem_meta| stem_meta.stem_commitment)
+            .sum()
+    }
+
+    // Returns true if a value is stored at the key, including a zero value
+    pub fn contains_key(&self, key: [u8; 32]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn contains_stem(&self, stem: [u8; 31]) -> bool {
+        self.storage.get_stem_meta(stem).is_some()
+    }
+
+    // Returns the depth of the stem in the trie, or None if the stem is not present.
+    // The depth is the number of branch nodes on the path from the root to the stem,
+    // so a stem which is a direct child of the root has a depth of 1.
+    //
+    // The stem metadata does not store the depth, so we walk the path using the stem
+    pub fn stem_depth(&self, stem: [u8; 31]) -> Option<u8> {
+        let mut branch_id = vec![];
+
+        for (index, path_index) in stem.iter().enumerate() {
+            let child = self.storage.get_branch_child(&branch_id, *path_index)?;
+
+            match child {
+                BranchChild::Stem(stem_id) => {
+                    // The slot could hold a different stem which shares a prefix with this one
+                    if stem_id != stem {
+                        return None;
+                    }
+                    return Some(index as u8 + 1);
+                }
+                BranchChild::Branch(_) => branch_id.push(*path_index),
+            }
+        }
+
+        None
+    }
+}
+
+impl<Storage, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+    // Returns the storage, so that backend specific methods can be called
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    // Returns the storage, so that backend specific maintenance such as compaction can be done.
+    //
+    // Writing to the storage directly bypasses the trie, so the commitments will not be updated.
+    // Use `audit` to check that the commitments are still consistent after such a write.
+    pub fn storage_mut(&mut self) -> &mut Storage {
+        self.cached_root.take();
+        &mut self.storage
+    }
+}
+
+// Builds a trie using the in-memory database and the basic committer.
+// This is convenient for tests and scripts, see `Trie::new_from_iter` to choose the backend
+impl std::iter::FromIterator<([u8; 32], [u8; 32])> for Trie<MemoryDb, BasicCommitter> {
+    fn from_iter<I: IntoIterator<Item = ([u8; 32], [u8; 32])>>(items: I) -> Self {
+        Trie::new_from_iter(MemoryDb::new(), BasicCommitter, items)
+    }
+}
+
+impl From<Vec<([u8; 32], [u8; 32])>> for Trie<MemoryDb, BasicCommitter> {
+    fn from(items: Vec<([u8; 32], [u8; 32])>) -> Self {
+        items.into_iter().collect()
+    }
+}
+
+// Returns the depth of the branch node at `path`, which is the length of the path.
+// Panics if the path is too long to be a branch node, instead of truncating its length
+fn depth_of_path(path: &[u8]) -> u8 {
+    assert!(
+        path.len() < MAX_DEPTH as usize,
+        "the branch node at path {} is deeper than the maximum depth of {}",
+        hex::encode(path),
+        MAX_DEPTH
+    );
+    path.len() as u8
+}
+
+// The value is prefixed with its length before hashing, so that the leaf value depends on
+// the length as well as the bytes of the value
+fn hash_value_bytes(value: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((value.len() as u64).to_le_bytes());
+    hasher.update(value);
+    hasher.finalize().into()
 }
 
 // Given a parent path such as [0,1,2]
 // and relative paths such as [5,6,7]
 // This method returns the following paths:
 // [0,1,2,5], [0,1,2,5,6], [0,1,2,5,6,7]
-// TODO: Is this hurting performance? If so can we rewrite it to be more efficient?
-// TODO Eagerly, we can use SmallVec32
-fn paths_from_relative(parent_path: Vec<u8>, relative_paths: Vec<u8>) -> Vec<Vec<u8>> {
+fn paths_from_relative(parent_path: &[u8], relative_paths: &[u8]) -> Vec<BranchId> {
     assert!(relative_paths.len() > 0);
 
-    let mut result = vec![parent_path.clone(); relative_paths.len()];
+    let mut result = vec![BranchId::from_slice(parent_path); relative_paths.len()];
     for (i, curr) in result.iter_mut().enumerate() {
         curr.extend_from_slice(&relative_paths[0..i + 1])
     }
@@ -477,7 +890,7 @@ fn paths_from_relative(parent_path: Vec<u8>, relative_paths: Vec<u8>) -> Vec<Vec
 pub(crate) struct LeafUpdated {
     old_val: Option<Vec<u8>>,
     new_value: Vec<u8>,
-    key: Vec<u8>,
+    key: Key,
 }
 #[derive(Debug)]
 pub(crate) struct StemUpdated {
@@ -490,8 +903,30 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
     pub fn compute_root(&self) -> Fr {
         // This covers the case when the tree is empty
         // If the number of stems is zero, then this branch will return zero
-        let root_node = self.storage.get_branch_meta(&vec![]).unwrap();
-        return root_node.hash_commitment;
+        *self.cached_root.get_or_init(|| {
+            let root_node = self.storage.get_branch_meta(&vec![]).unwrap();
+            root_node.hash_commitment
+        })
+    }
+
+    // Returns the commitment to the root node, whereas `compute_root` returns its hash
+    pub fn root_commitment(&self) -> EdwardsProjective {
+        self.storage.get_branch_meta(&[]).unwrap().commitment
+    }
+
+    // Returns the commitment of the branch node at the path, or None if there is no branch there.
+    // The root is at the empty path
+    pub fn commitment_at_path(&self, path: &[u8]) -> Option<EdwardsProjective> {
+        let (child_index, parent_path) = match path.split_last() {
+            Some(split) => split,
+            None => return Some(self.root_commitment()),
+        };
+
+        // The slot could also hold a stem, so we check the type of the child
+        match self.storage.get_branch_child(parent_path, *child_index)? {
+            BranchChild::Branch(branch_meta) => Some(branch_meta.commitment),
+            BranchChild::Stem(_) => None,
+        }
     }
     // Store the leaf, we return data on the old leaf, so that we can do the delta optimisation
     //
@@ -507,24 +942,39 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
             Some(vec) => {
                 // Check if they have just inserted the previous value
                 // if so, we early exit and return None
-                if &vec == &value {
+                if byte_arr::ct_eq(&vec, &value) {
                     return None;
                 }
                 Some(vec)
             }
-            None => None,
+            None => {
+                self.num_keys += 1;
+                None
+            }
         };
 
         Some(LeafUpdated {
             old_val,
             new_value: value.to_vec(),
-            key: key.to_vec(),
+            key: Key::from_arr(key),
         })
 
         // Storing a leaf means we need to change the stem table too
     }
 
     pub(crate) fn update_stem_table(&mut self, update_leaf: LeafUpdated, depth: u8) -> StemUpdated {
+        let stem = update_leaf.key.stem();
+        self.update_stem_table_with_leaves(stem, std::iter::once(update_leaf), depth)
+    }
+
+    // Updates the stem for all of the leaves which were updated under it.
+    // The deltas for the leaves are summed, so each commitment is only updated once
+    fn update_stem_table_with_leaves(
+        &mut self,
+        stem: [u8; 31],
+        leaf_updates: impl IntoIterator<Item = LeafUpdated>,
+        depth: u8,
+    ) -> StemUpdated {
         // If a leaf is updated, then we need to update the stem.
         // In particular, we need to update the commitment for that stem and the stem value
         //
@@ -534,45 +984,65 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
         // We can treat both cases as one because to compute the delta we do (new_value - old_value)
         // When the value has not changed, it's (new_value - 0)
         //
+        // C_1 is only updated if one of the leaves is in the first half of the stem,
+        // and C_2 if one of them is in the second half
+        let mut delta_c1: Option<EdwardsProjective> = None;
+        let mut delta_c2: Option<EdwardsProjective> = None;
+
+        for update_leaf in leaf_updates {
+            assert_eq!(update_leaf.key.stem(), stem);
+
+            // Split values into low_16 and high_16
+            let new_value_low_16 = update_leaf.new_value[0..16].to_vec();
+            let new_value_high_16 = update_leaf.new_value[16..32].to_vec();
+
+            // The low value of a leaf is committed to with a 2^128 marker.
+            // If the leaf was already present, the old value has the marker too, so it cancels out in the delta
+            let (old_value_low_16, old_value_high_16, old_marker) = match update_leaf.old_val {
+                Some(val) => (val[0..16].to_vec(), val[16..32].to_vec(), two_pow_128()),
+                None => (vec![0u8; 16], vec![0u8; 16], Fr::zero()),
+            };
 
-        // Split values into low_16 and high_16
-        let new_value_low_16 = update_leaf.new_value[0..16].to_vec();
-        let new_value_high_16 = update_leaf.new_value[16..32].to_vec();
-
-        let (old_value_low_16, old_value_high_16) = match update_leaf.old_val {
-            Some(val) => (val[0..16].to_vec(), val[16..32].to_vec()),
-            None => (vec![0u8; 16], vec![0u8; 16]),
-        };
-
-        // We need to compute two deltas
-        let delta_low = Fr::from_le_bytes_mod_order(&new_value_low_16) + two_pow_128()
-            - Fr::from_le_bytes_mod_order(&old_value_low_16);
-        let delta_high = Fr::from_le_bytes_mod_order(&new_value_high_16)
-            - Fr::from_le_bytes_mod_order(&old_value_high_16);
+            // We need to compute two deltas
+            let delta_low = Fr::from_le_bytes_mod_order(&new_value_low_16) + two_pow_128()
+                - Fr::from_le_bytes_mod_order(&old_value_low_16)
+                - old_marker;
+            let delta_high = Fr::from_le_bytes_mod_order(&new_value_high_16)
+                - Fr::from_le_bytes_mod_order(&old_value_high_16);
 
-        // We need to compute which group elements in the srs are being used
-        // We know that the first 128 values are mapped to the first 256 group elements
-        // and the last 128 values are mapped to the second 256 group elements
-        //
-        // So given our position is `0`, the values would map to (0,1)
-        // Given our position is `1` the values would map to (2,3)
-        // Given our position is `2`, the values would map to (4,5)
-        // Given our position is `n`. the values would map to (2n, 2n+1) where n < 128 ie 0 <= n <= 127
-        //
-        // For n >= 128, we mod 128 n then apply the same algorithm as above.
-        // Given our position is `255`, 255 mod 128 = 127. The values would be (254,255)
-        // Given our position is `128`, 128 mod 128 = 0. The values would be (0,1)
+            // We need to compute which group elements in the srs are being used
+            // We know that the first 128 values are mapped to the first 256 group elements
+            // and the last 128 values are mapped to the second 256 group elements
+            //
+            // So given our position is `0`, the values would map to (0,1)
+            // Given our position is `1` the values would map to (2,3)
+            // Given our position is `2`, the values would map to (4,5)
+            // Given our position is `n`. the values would map to (2n, 2n+1) where n < 128 ie 0 <= n <= 127
+            //
+            // For n >= 128, we mod 128 n then apply the same algorithm as above.
+            // Given our position is `255`, 255 mod 128 = 127. The values would be (254,255)
+            // Given our position is `128`, 128 mod 128 = 0. The values would be (0,1)
 
-        let position = update_leaf.key[31];
-        let pos_mod_128 = position % 128;
+            let position = update_leaf.key.suffix();
+            let pos_mod_128 = position % 128;
 
-        let low_index = 2 * pos_mod_128 as usize;
-        let high_index = low_index + 1;
+            let low_index = 2 * pos_mod_128 as usize;
+            let high_index = low_index + 1;
 
-        let generator_low = self.committer.scalar_mul(delta_low, low_index);
-        let generator_high = self.committer.scalar_mul(delta_high, high_index);
+            let generator_low = self.committer.scalar_mul(delta_low, low_index);
+            let generator_high = self.committer.scalar_mul(delta_high, high_index);
 
-        let stem: [u8; 31] = update_leaf.key[0..31].try_into().unwrap();
+            let delta = if position < 128 {
+                &mut delta_c1
+            } else {
+                &mut delta_c2
+            };
+            *delta = Some(
+                delta.unwrap_or_else(|| self.committer.identity_commitment())
+                    + generator_low
+                    + generator_high,
+            );
+        }
 
         let (C_1, old_hash_c1, C_2, old_hash_c2, stem_comm, old_hash_stem_comm) =
             match self.storage.get_stem_meta(stem) {
@@ -595,15 +1065,15 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
                     // This is the first leaf for the stem, so the C1, C2 commitments will be zero
                     // The stem commitment will be 1 * G_1 + stem * G_2
 
-                    let stem_comm = SRS[0]
+                    let stem_comm = self.committer.srs_point(0).into_projective()
                         + self
                             .committer
                             .scalar_mul(Fr::from_le_bytes_mod_order(&stem), 1);
                     (
-                        EdwardsProjective::zero(),
-                        group_to_field(&EdwardsProjective::zero()),
-                        EdwardsProjective::zero(),
-                        group_to_field(&EdwardsProjective::zero()),
+                        self.committer.identity_commitment(),
+                        identity_hash(),
+                        self.committer.identity_commitment(),
+                        identity_hash(),
                         stem_comm,
                         None,
                     )
@@ -611,41 +1081,35 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
             };
 
         // Compute the delta for the stem commitment
-        let (updated_C_1, new_hash_c1, updated_C_2, new_hash_c2, updated_stem_comm) =
-            if position < 128 {
-                // update C_1
-                let updated_C_1 = C_1 + generator_low + generator_high;
+        let mut updated_stem_comm = stem_comm;
+
+        // update C_1
+        let (updated_C_1, new_hash_c1) = match delta_c1 {
+            Some(delta_c1) => {
+                let updated_C_1 = C_1 + delta_c1;
                 let new_hash_c1 = group_to_field(&updated_C_1);
 
                 let c_1_delta = new_hash_c1 - old_hash_c1;
-                let c_1_point = self.committer.scalar_mul(c_1_delta, 2);
+                updated_stem_comm += self.committer.scalar_mul(c_1_delta, 2);
 
-                let updated_stem_comm = stem_comm + c_1_point;
+                (updated_C_1, new_hash_c1)
+            }
+            None => (C_1, old_hash_c1),
+        };
 
-                (
-                    updated_C_1,
-                    new_hash_c1,
-                    C_2,
-                    old_hash_c2,
-                    updated_stem_comm,
-                )
-            } else {
-                // update C_2
-                let updated_C_2 = C_2 + generator_low + generator_high;
+        // update C_2
+        let (updated_C_2, new_hash_c2) = match delta_c2 {
+            Some(delta_c2) => {
+                let updated_C_2 = C_2 + delta_c2;
                 let new_hash_c2 = group_to_field(&updated_C_2);
 
                 let c_2_delta = new_hash_c2 - old_hash_c2;
-                let c_2_point = self.committer.scalar_mul(c_2_delta, 3);
-
-                let updated_stem_comm = stem_comm + c_2_point;
-                (
-                    C_1,
-                    old_hash_c1,
-                    updated_C_2,
-                    new_hash_c2,
-                    updated_stem_comm,
-                )
-            };
+                updated_stem_comm += self.committer.scalar_mul(c_2_delta, 3);
+
+                (updated_C_2, new_hash_c2)
+            }
+            None => (C_2, old_hash_c2),
+        };
 
         let updated_hash_stem_comm = group_to_field(&updated_stem_comm);
 
@@ -669,6 +1133,27 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
         }
     }
 
+    // Registers a callback which is called with the path and new commitment of each branch node
+    // whose commitment is updated by an insert. Replaces any previously registered callback
+    pub fn set_on_branch_updated(
+        &mut self,
+        hook: impl Fn(&[u8], EdwardsProjective) + Send + Sync + 'static,
+    ) {
+        self.on_branch_updated = Some(BranchUpdateHook(std::sync::Arc::new(hook)))
+    }
+
+    pub fn clear_on_branch_updated(&mut self) {
+        self.on_branch_updated = None
+    }
+
+    // Stores the branch metadata and notifies the branch update callback, if one is registered
+    fn insert_branch_meta(&mut self, branch_id: BranchId, meta: BranchMeta, depth: u8) {
+        if let Some(hook) = &self.on_branch_updated {
+            (hook.0)(&branch_id, meta.commitment);
+        }
+        self.storage.insert_branch(branch_id.to_vec(), meta, depth);
+    }
+
     fn update_branch_table(
         &mut self,
         stem_update: StemUpdated,
@@ -694,7 +1179,7 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
 
         // Update the branch metadata
 
-        self.storage.insert_branch(
+        self.insert_branch_meta(
             branch_id.clone(),
             BranchMeta {
                 commitment: updated_branch_comm,
@@ -705,28 +1190,228 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
         let mut branch_child_id = branch_id;
         branch_child_id.push(branch_index);
         self.storage
-            .add_stem_as_branch_child(branch_child_id, stem_update.stem, depth);
+            .add_stem_as_branch_child(branch_child_id.to_vec(), stem_update.stem, depth);
 
         return hash_updated_branch_comm;
     }
 }
 
-impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+// Creating proofs only reads from the storage, so it can be done from a read-only snapshot or replica
+impl<Storage: ReadOnlyHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+    // Creates a proof for all of the keys. A key which is given more than once is only proven once,
+    // so the proof is the same as the proof for the keys without duplicates
+    //
+    // Panics if there are more distinct keys than the limit set with `set_max_proof_keys`,
+    // use `try_create_verkle_proof` to get an error instead
     pub fn create_verkle_proof(
         &self,
         keys: impl Iterator<Item = [u8; 32]>,
     ) -> crate::proof::VerkleProof {
+        match self.try_create_verkle_proof(keys) {
+            Ok(proof) => proof,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    // Same as `create_verkle_proof`, however an error is returned instead of creating a proof
+    // for more distinct keys than the limit set with `set_max_proof_keys`
+    pub fn try_create_verkle_proof(
+        &self,
+        keys: impl Iterator<Item = [u8; 32]>,
+    ) -> Result<crate::proof::VerkleProof, VerkleError> {
         use crate::proof::prover;
-        prover::create_verkle_proof(&self.storage, keys.collect())
+
+        let keys: Vec<_> = keys.collect();
+        self.check_max_proof_keys(&keys)?;
+
+        Ok(prover::create_verkle_proof(&self.storage, keys))
+    }
+
+    // Returns the root commitment along with a proof for the keys, which verifies against it.
+    //
+    // The root is read by the prover as part of creating the proof, instead of separately, so a
+    // write between reading the root and creating the proof cannot make them disagree.
+    // The trie cannot be modified through `&self`, however a storage which can also be written to
+    // from elsewhere, such as a database shared between processes, must give a consistent view of
+    // its nodes for the duration of the call
+    //
+    // Panics like `create_verkle_proof` if there are more distinct keys than the limit
+    pub fn get_root_and_proof(
+        &self,
+        keys: impl Iterator<Item = [u8; 32]>,
+    ) -> (EdwardsProjective, crate::proof::VerkleProof) {
+        use crate::proof::{prover, BranchPolynomialCache};
+
+        let keys: Vec<_> = keys.collect();
+        if let Err(err) = self.check_max_proof_keys(&keys) {
+            panic!("{}", err)
+        }
+
+        prover::create_verkle_proof_and_root(&self.storage, keys, &mut BranchPolynomialCache::new())
+    }
+
+    fn check_max_proof_keys(&self, keys: &[[u8; 32]]) -> Result<(), VerkleError> {
+        if let Some(max_keys) = self.max_proof_keys {
+            let num_keys = keys.iter().collect::<BTreeSet<_>>().len();
+            if num_keys > max_keys {
+                return Err(VerkleError::TooManyKeys { num_keys, max_keys });
+            }
+        }
+        Ok(())
+    }
+
+    // Limits the number of distinct keys that a proof can be created for, so that a large
+    // set of keys is rejected instead of creating a very large proof. `None` removes the limit
+    pub fn set_max_proof_keys(&mut self, max_proof_keys: Option<usize>) {
+        self.max_proof_keys = max_proof_keys
+    }
+
+    // Same as `create_verkle_proof`, however the polynomials for branch nodes are taken from the cache
+    // when possible, so proofs over keys with overlapping paths do not recompute them
+    pub fn create_verkle_proof_with_cache(
+        &self,
+        keys: impl Iterator<Item = [u8; 32]>,
+        cache: &mut crate::proof::BranchPolynomialCache,
+    ) -> crate::proof::VerkleProof {
+        use crate::proof::prover;
+        prover::create_verkle_proof_with_cache(&self.storage, keys.collect(), cache)
+    }
+
+    // Returns the commitments of the branch nodes on the key's path, starting with the root.
+    // If the key's stem is in the trie, then there is one commitment for each level of its depth.
+    //
+    // The path ends at the first slot which is empty or holds a stem, whether or not
+    // it is the key's stem, so this can also be used for keys which are not in the trie
+    pub fn get_path_commitments(&self, key: [u8; 32]) -> Vec<EdwardsProjective> {
+        let mut commitments = Vec::new();
+        let mut branch_id = vec![];
+
+        for path_index in Key::from_arr(key).stem().iter() {
+            let branch_meta = self
+                .storage
+                .get_branch_meta(&branch_id)
+                .expect("branch on the key's path is missing");
+            commitments.push(branch_meta.commitment);
+
+            match self.storage.get_branch_child(&branch_id, *path_index) {
+                Some(BranchChild::Branch(_)) => branch_id.push(*path_index),
+                Some(BranchChild::Stem(_)) | None => break,
+            }
+        }
+
+        commitments
+    }
+
+    // Same as `create_verkle_proof`, however statistics about the proof are also returned
+    pub fn create_verkle_proof_with_metrics(
+        &self,
+        keys: impl Iterator<Item = [u8; 32]>,
+    ) -> (crate::proof::VerkleProof, crate::proof::ProofMetrics) {
+        let keys: Vec<_> = keys.collect();
+        let num_keys = keys.len();
+
+        let branches: BTreeSet<Vec<u8>> = keys
+            .iter()
+            .flat_map(|key| {
+                let depth = self.get_path_commitments(*key).len();
+                (0..depth).map(move |i| key[0..i].to_vec())
+            })
+            .collect();
+
+        let now = std::time::Instant::now();
+        let proof = self.create_verkle_proof(keys.into_iter());
+
+        let metrics = crate::proof::ProofMetrics {
+            num_keys,
+            num_branches: branches.len(),
+            num_commitments: proof.num_commitments(),
+            duration: now.elapsed(),
+        };
+        (proof, metrics)
+    }
+
+    // Creates a proof for the leaves at the suffices in `range` under the stem.
+    // Suffices without a value are proven to be absent, which the stem commits to as zero.
+    //
+    // Panics if the range is empty, since a proof cannot be created for no keys
+    pub fn prove_index_range(
+        &self,
+        stem: [u8; 31],
+        range: std::ops::Range<u8>,
+    ) -> crate::proof::VerkleProof {
+        let keys = range.map(|suffix| {
+            let mut key = [0u8; 32];
+            key[0..31].copy_from_slice(&stem);
+            key[31] = suffix;
+            key
+        });
+        self.create_verkle_proof(keys)
+    }
+
+    // Returns the value stored at the key along with a proof for it.
+    // If the key is not in the trie, the proof is a proof of absence
+    //
+    // The value is the one read while creating the proof, so the key is only looked up once
+    pub fn get_with_proof(&self, key: [u8; 32]) -> (Option<[u8; 32]>, crate::proof::VerkleProof) {
+        use crate::proof::{prover, BranchPolynomialCache};
+
+        if let Err(err) = self.check_max_proof_keys(&[key]) {
+            panic!("{}", err)
+        }
+
+        let (mut values, proof) = prover::create_verkle_proof_and_values(
+            &self.storage,
+            vec![key],
+            &mut BranchPolynomialCache::new(),
+        );
+        (values.remove(&key).flatten(), proof)
     }
 }
+impl<S: BareMetalKVDb, PolyCommit: Committer> Trie<VerkleDb<S>, PolyCommit> {
+    // Creates a new Trie object, which keeps all nodes at `cache_depth` or above
+    // in the database's memory cache
+    pub fn new_with_cache_depth(mut db: VerkleDb<S>, pc: PolyCommit, cache_depth: u8) -> Self {
+        db.set_cache_depth(cache_depth);
+        Trie::new(db, pc)
+    }
+
+    // Reads the nodes at depth `levels` or above from the storage into the cache,
+    // see `VerkleDb::warm_cache`
+    pub fn warm_cache(&mut self, levels: u8) {
+        self.storage.warm_cache(levels)
+    }
+}
+
 impl<Storage: ReadWriteHigherDb + Flush, PolyCommit: Committer> Trie<Storage, PolyCommit> {
     // TODO: maybe make this private, and automatically flush
     // TODO after each insert. This will promote users to use insert()
-    // TODO If the amount of items in insert is too much, we will need to chop it up
-    // TODO and flush multiple times
+    //
+    // The root and the number of keys are stored with the flushed nodes,
+    // so that `verify_flush_integrity` can check them after the storage is reopened
     pub fn flush_database(&mut self) {
-        self.storage.flush()
+        let root = self.compute_root();
+        self.storage.insert_flush_checksum(FlushChecksum {
+            root,
+            num_keys: self.num_keys,
+        });
+        self.storage.flush();
+
+        if let Some((history, next_block_number)) = &mut self.root_history {
+            history.insert(*next_block_number, root);
+            *next_block_number += 1;
+        }
+    }
+
+    // Starts recording the root after each call to `flush_database`.
+    // The first flush is recorded as `starting_block`, and each flush after that as the next block
+    pub fn snapshot_root_history(&mut self, starting_block: u64) {
+        self.root_history = Some((BTreeMap::new(), starting_block));
+    }
+
+    // Returns the root that was recorded after the flush for `block_number`
+    pub fn root_at(&self, block_number: u64) -> Option<Fr> {
+        let (history, _) = self.root_history.as_ref()?;
+        history.get(&block_number).copied()
     }
 }
 #[cfg(test)]
@@ -740,7 +1425,7 @@ mod tests {
 
     use crate::database::memory_db::MemoryDb;
     use crate::database::ReadOnlyHigherDb;
-    use crate::{group_to_field, two_pow_128, SRS};
+    use crate::{group_to_field, two_pow_128, MAX_DEPTH, SRS};
     use crate::{trie::Trie, BasicCommitter};
 
     #[test]
@@ -1036,6 +1721,765 @@ mod tests {
         );
     }
 
+    #[test]
+    fn stem_depth_of_longest_chain() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // These keys share the first 30 bytes, so the chain insert creates
+        // 30 branch nodes below the root to separate their stems
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[30] = 1;
+        let stem_a: [u8; 31] = key_a[0..31].try_into().unwrap();
+        let stem_b: [u8; 31] = key_b[0..31].try_into().unwrap();
+
+        trie.insert(key_a, key_a);
+        assert_eq!(trie.stem_depth(stem_a), Some(1));
+
+        trie.insert(key_b, key_b);
+        assert!(trie.contains_stem(stem_a));
+        assert!(trie.contains_stem(stem_b));
+        assert_eq!(trie.stem_depth(stem_a), Some(31));
+        assert_eq!(trie.stem_depth(stem_b), Some(31));
+
+        // A stem which shares a prefix with the stored stems, but was never inserted
+        let mut stem_c = stem_a;
+        stem_c[30] = 2;
+        assert!(!trie.contains_stem(stem_c));
+        assert_eq!(trie.stem_depth(stem_c), None);
+    }
+
+    #[test]
+    fn branch_update_hook_fires_for_each_updated_branch() {
+        use std::sync::{Arc, Mutex};
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // key_a and key_b share their first byte, so the branch at [0] is above both of their stems
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[1] = 1;
+        trie.insert(key_a, key_a);
+        trie.insert(key_b, key_b);
+
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let recorder = updates.clone();
+        trie.set_on_branch_updated(move |branch_id, commitment| {
+            recorder
+                .lock()
+                .unwrap()
+                .push((branch_id.to_vec(), commitment))
+        });
+
+        trie.insert(key_a, [1u8; 32]);
+
+        // The branch under the stem is updated first, then the root
+        let updates = std::mem::take(&mut *updates.lock().unwrap());
+        let paths: Vec<_> = updates.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(paths, vec![vec![0], vec![]]);
+        for (path, commitment) in updates {
+            assert_eq!(
+                trie.storage.get_branch_meta(&path).unwrap().commitment,
+                commitment
+            );
+        }
+
+        trie.clear_on_branch_updated();
+        trie.insert(key_b, [1u8; 32]);
+    }
+
+    #[test]
+    fn insert_empty_is_distinct_from_absent() {
+        let key_a = [1u8; 32];
+        let mut key_b = key_a;
+        key_b[31] = 2;
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.insert(key_a, key_a);
+        let root_without_b = trie.compute_root();
+        assert!(!trie.contains_key(key_b));
+
+        assert!(trie.insert_empty(key_b));
+        assert!(trie.contains_key(key_b));
+        assert_eq!(trie.get(key_b), Some([0u8; 32]));
+        // The zero value still contributes 2^128 to C_1, so the root changes
+        assert_ne!(trie.compute_root(), root_without_b);
+
+        // Inserting the zero value again does not change the trie
+        assert!(!trie.insert_empty(key_b));
+    }
+
+    #[test]
+    fn commitment_at_path() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // key_a and key_b share their first two bytes, so there are branches at [0] and [0, 0]
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[2] = 1;
+        trie.insert(key_a, key_a);
+        trie.insert(key_b, key_b);
+
+        assert_eq!(trie.commitment_at_path(&[]), Some(trie.root_commitment()));
+        assert_eq!(
+            trie.commitment_at_path(&[0, 0]),
+            Some(trie.storage.get_branch_meta(&[0, 0]).unwrap().commitment)
+        );
+        assert_ne!(
+            trie.commitment_at_path(&[0, 0]),
+            trie.commitment_at_path(&[0])
+        );
+
+        // The slot at [0, 0, 0] holds a stem, and the slot at [1] is empty
+        assert_eq!(trie.commitment_at_path(&[0, 0, 0]), None);
+        assert_eq!(trie.commitment_at_path(&[1]), None);
+    }
+
+    #[test]
+    fn max_proof_keys() {
+        use crate::VerkleError;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+        let keys: Vec<_> = (0..4u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+
+        trie.set_max_proof_keys(Some(2));
+        assert!(trie
+            .try_create_verkle_proof(keys[0..2].iter().copied())
+            .is_ok());
+        // Duplicates are only proven once, so they do not count towards the limit
+        let duplicated_keys = [keys[0], keys[1], keys[0]];
+        assert!(trie
+            .try_create_verkle_proof(duplicated_keys.iter().copied())
+            .is_ok());
+        assert_eq!(
+            trie.try_create_verkle_proof(keys[0..3].iter().copied())
+                .err(),
+            Some(VerkleError::TooManyKeys {
+                num_keys: 3,
+                max_keys: 2
+            })
+        );
+
+        trie.set_max_proof_keys(None);
+        assert!(trie.try_create_verkle_proof(keys.into_iter()).is_ok());
+    }
+
+    #[test]
+    fn compute_root_is_cached_until_the_trie_changes() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+        let key = [1u8; 32];
+        trie.insert(key, key);
+        assert_eq!(trie.cached_root.get(), None);
+
+        let root = trie.compute_root();
+        assert_eq!(trie.cached_root.get(), Some(&root));
+
+        // Inserting the value which is already stored does not change the trie
+        assert!(!trie.insert(key, key));
+        assert_eq!(trie.cached_root.get(), Some(&root));
+        assert_eq!(trie.compute_root(), root);
+
+        assert!(trie.insert(key, [2u8; 32]));
+        assert_eq!(trie.cached_root.get(), None);
+        let new_root = trie.compute_root();
+        assert_ne!(new_root, root);
+        assert_eq!(
+            new_root,
+            trie.storage.get_branch_meta(&[]).unwrap().hash_commitment
+        );
+
+        trie.storage_mut();
+        assert_eq!(trie.cached_root.get(), None);
+    }
+
+    #[test]
+    fn reinsert_identical_value_under_existing_stem_is_noop() {
+        let key_a = [1u8; 32];
+        let mut key_b = key_a;
+        key_b[31] = 2;
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.insert(key_a, key_a);
+        trie.insert(key_b, key_b);
+        let root = trie.compute_root();
+
+        // key_b is stored under a stem which has another value, and is compared against its stored value
+        assert!(trie.create_insert_instructions(key_b, key_b).is_empty());
+        assert!(!trie.insert(key_b, key_b));
+        assert_eq!(trie.compute_root(), root);
+
+        // A different value under the same stem is still an update
+        assert!(trie.insert(key_b, key_a));
+        assert_ne!(trie.compute_root(), root);
+    }
+
+    #[test]
+    fn bulk_verify_keys() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+        for i in 0..3u8 {
+            trie.insert([i; 32], [i; 32]);
+        }
+
+        let claims = [
+            ([0u8; 32], [0u8; 32]),
+            ([1u8; 32], [2u8; 32]),
+            ([2u8; 32], [2u8; 32]),
+            ([3u8; 32], [3u8; 32]),
+        ];
+        assert_eq!(
+            trie.bulk_verify_keys(&claims),
+            vec![true, false, true, false]
+        );
+        assert!(trie.bulk_verify_keys(&[]).is_empty());
+    }
+
+    #[test]
+    fn get_path_commitments() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // key_a and key_b share their first 10 bytes, so their stems are at depth 11
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[10] = 1;
+        let key_c = [1u8; 32];
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        for key in [key_a, key_b, key_c] {
+            let commitments = trie.get_path_commitments(key);
+            let (_, depth) = trie.get_leaf_with_depth(key).unwrap();
+            assert_eq!(commitments.len(), depth as usize);
+            assert_eq!(commitments[0], root);
+        }
+
+        let commitments = trie.get_path_commitments(key_b);
+        assert_eq!(
+            commitments[10],
+            trie.storage.get_branch_meta(&[0u8; 10]).unwrap().commitment
+        );
+    }
+
+    #[test]
+    fn get_leaf_with_depth() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // key_a is alone under its first byte, while key_b and key_c share their first 10 bytes
+        let key_a = [1u8; 32];
+        let key_b = [0u8; 32];
+        let mut key_c = [0u8; 32];
+        key_c[10] = 1;
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+
+        assert_eq!(trie.get_leaf_with_depth(key_a), Some((key_a, 1)));
+        assert_eq!(trie.get_leaf_with_depth(key_b), Some((key_b, 11)));
+        assert_eq!(trie.get_leaf_with_depth(key_c), Some((key_c, 11)));
+
+        let mut absent_key = key_a;
+        absent_key[31] = 0;
+        assert_eq!(trie.get_leaf_with_depth(absent_key), None);
+    }
+
+    #[test]
+    // Updating only the high 16 bytes of a value, gives a zero delta for the low 16 bytes
+    fn zero_delta_update_matches_fresh_insert() {
+        let key = [3u8; 32];
+        let old_value = [1u8; 32];
+        let mut new_value = old_value;
+        new_value[31] = 2;
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.insert(key, old_value);
+        trie.insert(key, new_value);
+
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        expected_trie.insert(key, new_value);
+
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+    }
+
+    #[test]
+    // The 2^128 marker is part of the old and the new low value of an overwritten leaf,
+    // so it must cancel out in the delta instead of being added again on each overwrite
+    fn overwritten_leaves_match_fresh_trie() {
+        let key_a = [3u8; 32];
+        let mut key_b = key_a;
+        key_b[31] = 200;
+        let key_c = [4u8; 32];
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        for (key, value) in [
+            (key_a, [1u8; 32]),
+            (key_b, [2u8; 32]),
+            (key_c, [3u8; 32]),
+            (key_a, [5u8; 32]),
+            (key_a, [0u8; 32]),
+            (key_b, [6u8; 32]),
+        ] {
+            trie.insert(key, value);
+        }
+
+        let final_state = [(key_a, [0u8; 32]), (key_b, [6u8; 32]), (key_c, [3u8; 32])];
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        for (key, value) in final_state {
+            expected_trie.insert(key, value);
+        }
+
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+        assert!(trie.audit().is_empty());
+    }
+
+    #[test]
+    // Overwriting a leaf updates the stem with a delta, which must give the same
+    // stem as committing to all of its leaves from scratch
+    fn overwritten_leaf_matches_computed_stem_meta() {
+        let key_a = [3u8; 32];
+        let mut key_b = key_a;
+        key_b[31] = 200;
+        let stem = [3u8; 31];
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.insert(key_a, [1u8; 32]);
+        trie.insert(key_b, [2u8; 32]);
+        trie.insert(key_a, [5u8; 32]);
+        trie.insert(key_b, [0u8; 32]);
+
+        let stem_meta = trie.storage.get_stem_meta(stem).unwrap();
+        let expected = trie.compute_stem_meta(stem);
+        assert_eq!(stem_meta.C_1, expected.C_1);
+        assert_eq!(stem_meta.C_2, expected.C_2);
+        assert_eq!(stem_meta.stem_commitment, expected.stem_commitment);
+    }
+
+    #[test]
+    fn insert_reports_whether_trie_changed() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let key = [1u8; 32];
+
+        assert!(trie.insert(key, [1u8; 32]));
+        let root = trie.compute_root();
+
+        assert!(!trie.insert(key, [1u8; 32]));
+        assert_eq!(trie.compute_root(), root);
+
+        assert!(trie.insert(key, [2u8; 32]));
+        assert_ne!(trie.compute_root(), root);
+    }
+
+    #[test]
+    fn get_with_proof() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let present_key = [1u8; 32];
+        let absent_key = [2u8; 32];
+        trie.insert(present_key, [5u8; 32]);
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        for key in [present_key, absent_key] {
+            let (value, proof) = trie.get_with_proof(key);
+            assert_eq!(value, trie.get(key));
+
+            let (ok, _) = proof.check(vec![key], vec![value], root);
+            assert!(ok);
+        }
+    }
+
+    #[test]
+    fn get_root_and_proof() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let keys: Vec<_> = (0..3u8).map(|i| [i; 32]).collect();
+        for key in &keys {
+            trie.insert(*key, *key);
+        }
+        let absent_key = [5u8; 32];
+        let mut proven_keys = keys.clone();
+        proven_keys.push(absent_key);
+        let values: Vec<_> = proven_keys.iter().map(|key| trie.get(*key)).collect();
+
+        let (root, proof) = trie.get_root_and_proof(proven_keys.clone().into_iter());
+        assert_eq!(root, trie.root_commitment());
+        // The proof is the same as the one from `create_verkle_proof`
+        let expected_proof = trie.create_verkle_proof(proven_keys.clone().into_iter());
+        assert_eq!(format!("{:?}", proof), format!("{:?}", expected_proof));
+        let (ok, _) = proof
+            .clone()
+            .check(proven_keys.clone(), values.clone(), root);
+        assert!(ok);
+
+        // The returned root is a snapshot, it is not updated by later inserts
+        trie.insert(absent_key, absent_key);
+        assert_ne!(root, trie.root_commitment());
+    }
+
+    #[test]
+    fn apply_state_diff_matches_individual_inserts() {
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[31] = 200;
+        let key_c = [1u8; 32];
+        let key_absent = [2u8; 32];
+
+        // key_a is written twice, only the last write should be applied
+        let diff = vec![
+            (key_c, Some([3u8; 32])),
+            (key_a, Some([1u8; 32])),
+            (key_absent, None),
+            (key_b, Some([2u8; 32])),
+            (key_a, Some([4u8; 32])),
+        ];
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.apply_state_diff(&diff).unwrap();
+
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        expected_trie.insert(key_a, [4u8; 32]);
+        expected_trie.insert(key_b, [2u8; 32]);
+        expected_trie.insert(key_c, [3u8; 32]);
+
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+        assert_eq!(trie.get(key_a), Some([4u8; 32]));
+        assert_eq!(trie.get(key_absent), None);
+    }
+
+    #[test]
+    fn new_from_iter_matches_individual_inserts() {
+        // Keys 0 and 1 have two values under the same stem, and only the last value of key 2 is kept
+        let mut items: Vec<([u8; 32], [u8; 32])> =
+            (0..8u8).rev().map(|i| ([i; 32], [i + 1; 32])).collect();
+        for i in 0..2u8 {
+            let mut key = [i; 32];
+            key[31] = 255;
+            items.push((key, [9u8; 32]));
+        }
+        items.push(([2u8; 32], [10u8; 32]));
+
+        let trie = Trie::new_from_iter(MemoryDb::new(), BasicCommitter, items.clone());
+
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        for (key, value) in items {
+            expected_trie.insert(key, value);
+        }
+
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+    }
+
+    #[test]
+    fn collect_into_trie() {
+        let items: Vec<([u8; 32], [u8; 32])> = (0..4u8).map(|i| ([i; 32], [i + 1; 32])).collect();
+
+        let trie: Trie<MemoryDb, BasicCommitter> = items.iter().copied().collect();
+        for (key, value) in &items {
+            assert_eq!(trie.get(*key), Some(*value));
+        }
+
+        let from_vec = Trie::from(items);
+        assert_eq!(from_vec.compute_root(), trie.compute_root());
+    }
+
+    #[test]
+    fn apply_state_diff_updates_branch_once_per_stem() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.insert([0u8; 32], [0u8; 32]);
+
+        let num_root_updates = Arc::new(AtomicUsize::new(0));
+        let counter = num_root_updates.clone();
+        trie.set_on_branch_updated(move |branch_id, _| {
+            if branch_id.is_empty() {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        // All of the keys share the stem which is already in the trie
+        let diff: Vec<_> = (1..4u8)
+            .map(|suffix| {
+                let mut key = [0u8; 32];
+                key[31] = suffix;
+                (key, Some([suffix; 32]))
+            })
+            .collect();
+        trie.apply_state_diff(&diff).unwrap();
+
+        assert_eq!(num_root_updates.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn apply_state_diff_rejects_deleting_present_key() {
+        use crate::VerkleError;
+
+        let key = [1u8; 32];
+        let other_key = [2u8; 32];
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.insert(key, key);
+        let root = trie.compute_root();
+
+        assert_eq!(
+            trie.apply_state_diff(&[(other_key, Some(other_key)), (key, None)]),
+            Err(VerkleError::DeleteNotSupported { key })
+        );
+
+        // The write in the rejected diff is not applied
+        assert_eq!(trie.compute_root(), root);
+        assert_eq!(trie.get(other_key), None);
+    }
+
+    #[test]
+    fn update_hot_key_matches_insert() {
+        let hot_key = [0u8; 32];
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+        for counter in 0..5u8 {
+            let value = [counter; 32];
+            trie.update_hot_key(hot_key, value);
+            expected_trie.insert(hot_key, value);
+            assert_eq!(trie.compute_root(), expected_trie.compute_root());
+
+            // This key shares a prefix with the hot key and moves its stem deeper into the trie
+            let mut other_key = [0u8; 32];
+            other_key[counter as usize] = 1;
+            trie.insert(other_key, other_key);
+            expected_trie.insert(other_key, other_key);
+        }
+
+        trie.update_hot_key(hot_key, [9u8; 32]);
+        expected_trie.insert(hot_key, [9u8; 32]);
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+        assert!(trie.audit().is_empty());
+    }
+
+    #[test]
+    fn insert_bytes_of_any_length() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let short_key = [1u8; 32];
+        let long_key = [2u8; 32];
+        let short_value = b"short".to_vec();
+        let long_value: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+
+        assert!(trie.insert_bytes(short_key, &short_value).unwrap());
+        assert!(trie.insert_bytes(long_key, &long_value).unwrap());
+        assert_eq!(trie.get_bytes(short_key), Some(short_value.clone()));
+        assert_eq!(trie.get_bytes(long_key), Some(long_value));
+        assert!(!trie.insert_bytes(short_key, &short_value).unwrap());
+
+        // The empty value is different from a missing value
+        assert!(trie.insert_bytes(short_key, &[]).unwrap());
+        assert_eq!(trie.get_bytes(short_key), Some(Vec::new()));
+        assert_eq!(trie.get_bytes([3u8; 32]), None);
+
+        // The leaf holds the hash of the value, which is committed to like any other value
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        expected_trie.insert(short_key, super::hash_value_bytes(&[]));
+        expected_trie.insert(long_key, trie.get(long_key).unwrap());
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+        assert_eq!(expected_trie.get_bytes(short_key), None);
+
+        // The length is hashed too, so zero bytes of different lengths are different values
+        assert_ne!(
+            super::hash_value_bytes(&[0u8; 1]),
+            super::hash_value_bytes(&[0u8; 2])
+        );
+    }
+
+    #[test]
+    fn prepare_and_commit_matches_insert() {
+        use rayon::prelude::*;
+
+        // Two of the keys share a stem and two more share a prefix, so some of the
+        // prepared inserts are stale by the time that they are committed
+        let mut keys = vec![[1u8; 32], [2u8; 32], [0u8; 32]];
+        let mut key = [1u8; 32];
+        key[31] = 5;
+        keys.push(key);
+        key[1] = 0;
+        keys.push(key);
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie.insert([0u8; 32], [9u8; 32]);
+        expected_trie.insert([0u8; 32], [9u8; 32]);
+
+        let prepared: Vec<_> = keys
+            .par_iter()
+            .map(|key| trie.prepare(*key, *key))
+            .collect();
+        for (key, prepared) in keys.iter().zip(prepared) {
+            assert_eq!(trie.commit(prepared), expected_trie.insert(*key, *key));
+            assert_eq!(trie.compute_root(), expected_trie.compute_root());
+        }
+
+        // Preparing an insert does not modify the trie
+        let root = trie.compute_root();
+        let prepared = trie.prepare([3u8; 32], [3u8; 32]);
+        assert_eq!(trie.get([3u8; 32]), None);
+        assert_eq!(trie.compute_root(), root);
+        assert!(trie.commit(prepared));
+        assert_eq!(trie.get([3u8; 32]), Some([3u8; 32]));
+
+        // Preparing the value which is already stored creates no instructions
+        let prepared = trie.prepare([3u8; 32], [3u8; 32]);
+        assert!(!trie.commit(prepared));
+        assert!(trie.audit().is_empty());
+    }
+
+    #[test]
+    fn insert_stem_values_matches_individual_inserts() {
+        let stem = [7u8; 31];
+        // Values in both halves of the stem, so C_1 and C_2 are both updated
+        let values = [
+            (0u8, [1u8; 32]),
+            (5, [2u8; 32]),
+            (127, [3u8; 32]),
+            (128, [4u8; 32]),
+            (255, [0u8; 32]),
+        ];
+        let key_with_suffix = |suffix: u8| {
+            let mut key = [0u8; 32];
+            key[0..31].copy_from_slice(&stem);
+            key[31] = suffix;
+            key
+        };
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        let mut expected_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        // This key shares a prefix with the stem, so the stem is not a child of the root
+        let mut other_key = key_with_suffix(0);
+        other_key[1] = 0;
+        trie.insert(other_key, other_key);
+        expected_trie.insert(other_key, other_key);
+
+        // The stem is added by the first value
+        assert!(trie.insert_stem_values(stem, &values));
+        for (suffix, value) in values {
+            expected_trie.insert(key_with_suffix(suffix), value);
+        }
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+        for (suffix, value) in values {
+            assert_eq!(trie.get(key_with_suffix(suffix)), Some(value));
+        }
+
+        // Updates the stem which is now in the trie, one of the values is unchanged
+        let updates = [(5u8, [2u8; 32]), (6, [6u8; 32]), (128, [9u8; 32])];
+        assert!(trie.insert_stem_values(stem, &updates));
+        for (suffix, value) in updates {
+            expected_trie.insert(key_with_suffix(suffix), value);
+        }
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+        assert!(trie.audit().is_empty());
+
+        assert!(!trie.insert_stem_values(stem, &updates));
+        assert!(!trie.insert_stem_values([8u8; 31], &[]));
+        assert_eq!(trie.compute_root(), expected_trie.compute_root());
+    }
+
+    #[test]
+    fn commitment_over_keys() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+        let key_a = [1u8; 32];
+        let mut key_b = key_a;
+        key_b[31] = 2;
+        let key_c = [3u8; 32];
+        let absent_key = [4u8; 32];
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+
+        // key_a and key_b share a stem, so its commitment is only added once
+        let stem_a: [u8; 31] = key_a[0..31].try_into().unwrap();
+        let stem_c: [u8; 31] = key_c[0..31].try_into().unwrap();
+        let expected = trie.storage.get_stem_meta(stem_a).unwrap().stem_commitment
+            + trie.storage.get_stem_meta(stem_c).unwrap().stem_commitment;
+
+        let got = trie.commitment_over_keys(&[key_a, key_b, key_c, absent_key]);
+        assert_eq!(got, expected);
+        assert_eq!(trie.commitment_over_keys(&[]), EdwardsProjective::zero());
+    }
+
+    #[test]
+    fn storage_accessors() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        // The root branch is inserted when the trie is created
+        assert_eq!(trie.storage().num_items(), 1);
+
+        // One leaf, one stem and the stem as a child of the root
+        trie.insert([1u8; 32], [1u8; 32]);
+        assert_eq!(trie.storage().num_items(), 4);
+
+        trie.storage_mut().clear();
+        assert_eq!(trie.storage().num_items(), 0);
+    }
+
+    #[test]
+    fn root_history_records_each_flush() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+        // Flushes before the history is enabled are not recorded
+        trie.insert([0u8; 32], [0u8; 32]);
+        trie.flush_database();
+        trie.snapshot_root_history(10);
+        assert_eq!(trie.root_at(10), None);
+
+        trie.insert([1u8; 32], [1u8; 32]);
+        trie.flush_database();
+        let intermediate_root = trie.compute_root();
+
+        trie.insert([2u8; 32], [2u8; 32]);
+        trie.flush_database();
+        let final_root = trie.compute_root();
+
+        assert_ne!(intermediate_root, final_root);
+        assert_eq!(trie.root_at(10), Some(intermediate_root));
+        assert_eq!(trie.root_at(11), Some(final_root));
+        assert_eq!(trie.root_at(12), None);
+    }
+
+    #[test]
+    fn prove_index_range() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+        let stem = [7u8; 31];
+        let key_at = |suffix: u8| {
+            let mut key = [0u8; 32];
+            key[0..31].copy_from_slice(&stem);
+            key[31] = suffix;
+            key
+        };
+        trie.insert(key_at(12), [12u8; 32]);
+        trie.insert(key_at(15), [15u8; 32]);
+        trie.insert(key_at(30), [30u8; 32]);
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        let proof = trie.prove_index_range(stem, 10..20);
+
+        let keys: Vec<_> = (10..20).map(key_at).collect();
+        let values: Vec<_> = keys.iter().map(|key| trie.get(*key)).collect();
+        assert_eq!(values.iter().filter(|value| value.is_some()).count(), 2);
+
+        let (ok, _) = proof.check(keys, values, root);
+        assert!(ok);
+    }
+
     #[test]
     fn empty_trie() {
         // An empty tree should return zero as the root
@@ -1046,6 +2490,49 @@ mod tests {
         assert_eq!(trie.compute_root(), Fr::zero())
     }
 
+    #[test]
+    fn deepest_chain_stays_within_max_depth() {
+        use crate::database::ReadOnlyHigherDb;
+
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+        // key_a and key_b have stems that only differ in byte 30
+        // key_c has the same stem as key_a, so it does not create another branch node
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[30] = 1;
+        let mut key_c = [0u8; 32];
+        key_c[31] = 1;
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+
+        let stem_a: [u8; 31] = key_a[0..31].try_into().unwrap();
+        let stem_b: [u8; 31] = key_b[0..31].try_into().unwrap();
+        assert_eq!(trie.stem_depth(stem_a), Some(MAX_DEPTH));
+        assert_eq!(trie.stem_depth(stem_b), Some(MAX_DEPTH));
+
+        // There is a branch node at every depth up to the deepest one
+        for depth in 0..MAX_DEPTH as usize {
+            let path = &stem_a[0..depth];
+            assert!(trie.storage.get_branch_meta(path).is_some());
+            assert_eq!(super::depth_of_path(path), depth as u8);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "deeper than the maximum depth")]
+    fn depth_of_path_rejects_paths_past_max_depth() {
+        super::depth_of_path(&[0u8; MAX_DEPTH as usize]);
+    }
+
+    #[test]
+    #[should_panic(expected = "deeper than the maximum depth")]
+    fn depth_of_path_does_not_truncate_long_paths() {
+        // The length of this path truncates to 0 as a u8
+        super::depth_of_path(&[0u8; 256]);
+    }
+
     #[test]
     fn simple_rel_paths() {
         let parent = vec![0, 1, 2];
@@ -1055,11 +2542,11 @@ mod tests {
             vec![0, 1, 2, 5, 6],
             vec![0, 1, 2, 5, 6, 7],
         ];
-        let result = super::paths_from_relative(parent, rel);
+        let result = super::paths_from_relative(&parent, &rel);
 
         assert_eq!(result.len(), expected.len());
         for (got, expected) in result.into_iter().zip(expected) {
-            assert_eq!(got, expected)
+            assert_eq!(got.as_slice(), expected.as_slice())
         }
     }
 }
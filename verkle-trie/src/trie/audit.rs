@@ -0,0 +1,289 @@
+use super::Trie;
+use crate::database::{BranchChild, BranchMeta, ReadWriteHigherDb, StemMeta};
+use crate::{byte_arr::Value, group_to_field, Committer};
+use ark_ec::AffineCurve;
+use ark_ff::{One, PrimeField, Zero};
+use bandersnatch::Fr;
+
+// An inconsistency found when recomputing a node's commitments from its children
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditError {
+    // The commitment or hash stored for the branch at `path` does not match
+    // the commitment computed from its children
+    Branch { path: Vec<u8> },
+    // The C_1, C_2 or stem commitment stored for the stem at `path` does not match
+    // the commitments computed from its leaves
+    Stem { path: Vec<u8>, stem: [u8; 31] },
+}
+
+impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+    // Walks every branch and stem in the trie, recomputes their commitments from scratch
+    // and returns every node whose stored commitments do not match.
+    //
+    // This does not use the delta optimisation, so it is slow. It is meant to be used
+    // when we suspect that there is a bug in the delta updates.
+    pub fn audit(&self) -> Vec<AuditError> {
+        let mut errors = Vec::new();
+
+        let root_meta = self.storage.get_branch_meta(&[]).unwrap();
+        self.audit_branch(vec![], root_meta, &mut errors);
+
+        errors
+    }
+
+    // Checks a reopened trie against the checksum which was stored by the last `flush_database`.
+    //
+    // The stored root and number of keys must match the trie, and every commitment must match
+    // the leaves below it. So this reads the whole trie, and it returns false if the storage
+    // does not have a checksum.
+    pub fn verify_flush_integrity(&self) -> bool {
+        let checksum = match self.storage.get_flush_checksum() {
+            Some(checksum) => checksum,
+            None => return false,
+        };
+        checksum.root == self.compute_root()
+            && checksum.num_keys == self.keys().count() as u64
+            && self.audit().is_empty()
+    }
+
+    fn audit_branch(&self, path: Vec<u8>, meta: BranchMeta, errors: &mut Vec<AuditError>) {
+        let mut children_hashes = vec![Fr::zero(); 256];
+
+        for (index, child) in self.storage.get_branch_children(&path) {
+            let mut child_path = path.clone();
+            child_path.push(index);
+
+            children_hashes[index as usize] = match child {
+                BranchChild::Branch(child_meta) => {
+                    self.audit_branch(child_path, child_meta, errors);
+                    child_meta.hash_commitment
+                }
+                BranchChild::Stem(stem) => {
+                    let stem_meta = self.storage.get_stem_meta(stem).unwrap();
+                    if stem_meta != self.compute_stem_meta(stem) {
+                        errors.push(AuditError::Stem {
+                            path: child_path,
+                            stem,
+                        });
+                    }
+                    stem_meta.hash_stem_commitment
+                }
+            };
+        }
+
+        let commitment = self.committer.commit_lagrange(&children_hashes);
+        if commitment != meta.commitment || group_to_field(&commitment) != meta.hash_commitment {
+            errors.push(AuditError::Branch { path });
+        }
+    }
+
+    // Recomputes the stem commitment from the stored C_1 and C_2 commitments and compares it
+    // to the stored stem commitment. Unlike `audit`, this does not read the leaves, so it
+    // only detects corruption in the stem metadata itself.
+    //
+    // Returns false if the stem is not in the trie
+    pub fn verify_stem_commitment(&self, stem: [u8; 31]) -> bool {
+        let stem_meta = match self.storage.get_stem_meta(stem) {
+            Some(stem_meta) => stem_meta,
+            None => return false,
+        };
+
+        // The stem commitment is: 1 * G_0 + stem * G_1 + hash(C1) * G_2 + hash(C2) * G_3
+        let stem_commitment = self.committer.srs_point(0).into_projective()
+            + self
+                .committer
+                .scalar_mul(Fr::from_le_bytes_mod_order(&stem), 1)
+            + self.committer.scalar_mul(group_to_field(&stem_meta.C_1), 2)
+            + self.committer.scalar_mul(group_to_field(&stem_meta.C_2), 3);
+
+        stem_commitment == stem_meta.stem_commitment
+    }
+
+    // Computes the metadata for a stem using all of the leaves stored under it
+    pub(crate) fn compute_stem_meta(&self, stem: [u8; 31]) -> StemMeta {
+        // C_1 commits to the values at suffices 0..128 and C_2 to the values at 128..256
+        // Each value is split into a low and high 16 bytes, see `update_stem_table`
+        let mut c_1_evals = vec![Fr::zero(); 256];
+        let mut c_2_evals = vec![Fr::zero(); 256];
+
+        for (suffix, value) in self.storage.get_stem_children(stem) {
+            let evals = if suffix < 128 {
+                &mut c_1_evals
+            } else {
+                &mut c_2_evals
+            };
+            let low_index = 2 * (suffix % 128) as usize;
+            let (value_low, value_high) = Value::from_arr(value).to_field_pair();
+            evals[low_index] = value_low;
+            evals[low_index + 1] = value_high;
+        }
+
+        let c_1 = self.committer.commit_lagrange(&c_1_evals);
+        let c_2 = self.committer.commit_lagrange(&c_2_evals);
+        let hash_c1 = group_to_field(&c_1);
+        let hash_c2 = group_to_field(&c_2);
+
+        // The stem commitment is: 1 * G_0 + stem * G_1 + hash(C1) * G_2 + hash(C2) * G_3
+        let mut stem_evals = vec![Fr::zero(); 256];
+        stem_evals[0] = Fr::one();
+        stem_evals[1] = Fr::from_le_bytes_mod_order(&stem);
+        stem_evals[2] = hash_c1;
+        stem_evals[3] = hash_c2;
+        let stem_commitment = self.committer.commit_lagrange(&stem_evals);
+
+        StemMeta {
+            C_1: c_1,
+            hash_c1,
+            C_2: c_2,
+            hash_c2,
+            stem_commitment,
+            hash_stem_commitment: group_to_field(&stem_commitment),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditError;
+    use crate::database::memory_db::MemoryDb;
+    use crate::database::{BranchMeta, ReadOnlyHigherDb, WriteOnlyHigherDb};
+    use crate::{trie::Trie, BasicCommitter, Committer, SRS};
+    use ark_ec::ProjectiveCurve;
+    use ark_ff::{PrimeField, Zero};
+    use bandersnatch::{EdwardsProjective, Fr};
+    use std::convert::TryInto;
+
+    #[test]
+    fn audit_reports_all_corrupted_nodes() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        // key_a and key_b share their first byte, so they will be
+        // placed under a branch node at path [0]
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[1] = 1;
+        let key_c = [1u8; 32];
+        let stem_c: [u8; 31] = key_c[0..31].try_into().unwrap();
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+
+        // A trie which was only modified through inserts should be consistent
+        assert!(trie.audit().is_empty());
+
+        // Corrupt the commitments for the root, the branch at [0] and the stem at [1]
+        for path in [vec![], vec![0]] {
+            let meta = trie.storage.get_branch_meta(&path).unwrap();
+            let depth = path.len() as u8;
+            trie.storage.insert_branch(
+                path,
+                BranchMeta {
+                    commitment: meta.commitment + SRS[5],
+                    hash_commitment: meta.hash_commitment,
+                },
+                depth,
+            );
+        }
+        let mut stem_meta = trie.storage.get_stem_meta(stem_c).unwrap();
+        stem_meta.C_1 += SRS[5];
+        trie.storage.insert_stem(stem_c, stem_meta, 1);
+
+        let errors = trie.audit();
+        assert_eq!(errors.len(), 3);
+        assert!(errors.contains(&AuditError::Branch { path: vec![] }));
+        assert!(errors.contains(&AuditError::Branch { path: vec![0] }));
+        assert!(errors.contains(&AuditError::Stem {
+            path: vec![1],
+            stem: stem_c
+        }));
+    }
+
+    #[test]
+    fn verify_flush_integrity_detects_corrupted_leaf() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        for i in 0..4u8 {
+            trie.insert([i; 32], [i; 32]);
+        }
+        trie.flush_database();
+        assert!(trie.verify_flush_integrity());
+
+        // Corrupt a leaf in the backend, without updating the commitments above it
+        let mut storage = trie.storage;
+        storage.insert_leaf([2u8; 32], [9u8; 32], 1);
+
+        let reopened = Trie::new(storage, BasicCommitter);
+        assert_eq!(reopened.get([2u8; 32]), Some([9u8; 32]));
+        assert!(!reopened.verify_flush_integrity());
+    }
+
+    #[test]
+    fn verify_flush_integrity_compares_the_last_flush() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        // Nothing has been flushed, so there is no checksum to compare against
+        assert!(!trie.verify_flush_integrity());
+
+        trie.insert([1u8; 32], [1u8; 32]);
+        trie.flush_database();
+        assert!(trie.verify_flush_integrity());
+
+        // The trie no longer matches the root and key count of the last flush
+        trie.insert([2u8; 32], [2u8; 32]);
+        assert!(!trie.verify_flush_integrity());
+
+        trie.flush_database();
+        assert!(trie.verify_flush_integrity());
+        assert_eq!(trie.storage.get_flush_checksum().unwrap().num_keys, 2);
+    }
+
+    // Commits with the generators of the SRS in reverse order, so that it does not agree
+    // with the global SRS at any index that a stem commitment uses
+    struct ReversedCommitter;
+
+    impl Committer for ReversedCommitter {
+        fn commit_lagrange(&self, evaluations: &[Fr]) -> EdwardsProjective {
+            let mut res = EdwardsProjective::zero();
+            for (index, val) in evaluations.iter().enumerate() {
+                res += self.scalar_mul(*val, index)
+            }
+            res
+        }
+
+        fn scalar_mul(&self, value: Fr, lagrange_index: usize) -> EdwardsProjective {
+            SRS[SRS.len() - 1 - lagrange_index].mul(value.into_repr())
+        }
+    }
+
+    #[test]
+    fn stem_commitments_use_the_committer_generators() {
+        let mut trie = Trie::new(MemoryDb::new(), ReversedCommitter);
+
+        let key = [1u8; 32];
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+        trie.insert(key, key);
+
+        assert!(trie.verify_stem_commitment(stem));
+        assert_eq!(
+            trie.storage.get_stem_meta(stem),
+            Some(trie.compute_stem_meta(stem))
+        );
+        assert!(trie.audit().is_empty());
+    }
+
+    #[test]
+    fn verify_stem_commitment_detects_corrupted_c_1() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(db, BasicCommitter);
+
+        let key = [1u8; 32];
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+        trie.insert(key, key);
+        assert!(trie.verify_stem_commitment(stem));
+        assert!(!trie.verify_stem_commitment([2u8; 31]));
+
+        let mut stem_meta = trie.storage.get_stem_meta(stem).unwrap();
+        stem_meta.C_1 += SRS[5];
+        trie.storage.insert_stem(stem, stem_meta, 1);
+        assert!(!trie.verify_stem_commitment(stem));
+    }
+}
@@ -0,0 +1,145 @@
+use super::Trie;
+use crate::database::{BranchChild, ReadOnlyHigherDb};
+use crate::Committer;
+use ark_serialize::CanonicalSerialize;
+use bandersnatch::Fr;
+use std::fmt::Write;
+
+impl<Storage: ReadOnlyHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+    // Returns a GraphViz graph of the branches, stems and leaves in the trie.
+    // Each edge is labelled with the child index, and each branch and stem with
+    // the first bytes of the hash of its commitment
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph verkle {\n");
+
+        let root_meta = self.storage.get_branch_meta(&[]).unwrap();
+        writeln!(
+            dot,
+            "  \"b_\" [label=\"root\\n{}\"];",
+            truncated_hash(&root_meta.hash_commitment)
+        )
+        .unwrap();
+        self.write_branch_children(&[], &mut dot);
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn write_branch_children(&self, path: &[u8], dot: &mut String) {
+        let node_id = format!("b_{}", hex::encode(path));
+
+        let mut children = self.storage.get_branch_children(path);
+        children.sort_by_key(|(index, _)| *index);
+
+        for (index, child) in children {
+            let mut child_path = path.to_vec();
+            child_path.push(index);
+
+            match child {
+                BranchChild::Branch(meta) => {
+                    let child_id = format!("b_{}", hex::encode(&child_path));
+                    writeln!(
+                        dot,
+                        "  \"{}\" [label=\"branch {}\\n{}\"];",
+                        child_id,
+                        hex::encode(&child_path),
+                        truncated_hash(&meta.hash_commitment)
+                    )
+                    .unwrap();
+                    writeln!(
+                        dot,
+                        "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                        node_id, child_id, index
+                    )
+                    .unwrap();
+                    self.write_branch_children(&child_path, dot);
+                }
+                BranchChild::Stem(stem) => {
+                    let stem_id = format!("s_{}", hex::encode(stem));
+                    let stem_meta = self.storage.get_stem_meta(stem).unwrap();
+                    writeln!(
+                        dot,
+                        "  \"{}\" [shape=box, label=\"stem {}\\n{}\"];",
+                        stem_id,
+                        hex::encode(&stem[0..4]),
+                        truncated_hash(&stem_meta.hash_stem_commitment)
+                    )
+                    .unwrap();
+                    writeln!(
+                        dot,
+                        "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                        node_id, stem_id, index
+                    )
+                    .unwrap();
+                    self.write_stem_leaves(stem, &stem_id, dot);
+                }
+            }
+        }
+    }
+
+    fn write_stem_leaves(&self, stem: [u8; 31], stem_id: &str, dot: &mut String) {
+        let mut leaves = self.storage.get_stem_children(stem);
+        leaves.sort_by_key(|(suffix, _)| *suffix);
+
+        for (suffix, value) in leaves {
+            let leaf_id = format!("l_{}{:02x}", hex::encode(stem), suffix);
+            writeln!(
+                dot,
+                "  \"{}\" [shape=plaintext, label=\"{}\"];",
+                leaf_id,
+                hex::encode(&value[0..4])
+            )
+            .unwrap();
+            writeln!(
+                dot,
+                "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                stem_id, leaf_id, suffix
+            )
+            .unwrap();
+        }
+    }
+}
+
+// The first 4 bytes of the serialised hash, which is enough to tell nodes apart when debugging
+fn truncated_hash(hash: &Fr) -> String {
+    let mut bytes = [0u8; 32];
+    hash.serialize(&mut bytes[..])
+        .expect("could not serialise field element into a 32 byte array");
+    hex::encode(&bytes[0..4])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::memory_db::MemoryDb;
+    use crate::{trie::Trie, BasicCommitter};
+
+    #[test]
+    fn to_dot_has_a_node_for_each_leaf() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+
+        // key_a and key_b share their first byte, and key_c is under the same stem as key_a
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[1] = 1;
+        let mut key_c = key_a;
+        key_c[31] = 1;
+        for key in [key_a, key_b, key_c] {
+            trie.insert(key, key);
+        }
+
+        let dot = trie.to_dot();
+        assert!(dot.starts_with("digraph verkle {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("\"b_\" [label=\"root\\n"));
+        assert!(dot.contains("\"b_\" -> \"b_00\" [label=\"0\"];"));
+
+        let count_nodes = |prefix: &str| {
+            dot.lines()
+                .filter(|line| line.starts_with(prefix) && !line.contains("->"))
+                .count()
+        };
+        assert_eq!(count_nodes("  \"b_"), 2);
+        assert_eq!(count_nodes("  \"s_"), 2);
+        assert_eq!(count_nodes("  \"l_"), 3);
+    }
+}
@@ -0,0 +1,156 @@
+use super::Trie;
+use crate::database::{BranchChild, ReadOnlyHigherDb};
+use crate::Committer;
+use sha2::{Digest, Sha256};
+
+impl<Storage: ReadOnlyHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+    // Returns all of the key-value pairs in the trie, sorted by key
+    pub fn iter(&self) -> Leaves<'_, Storage> {
+        let mut leaves = Leaves {
+            storage: &self.storage,
+            pending: Vec::new(),
+            stem_leaves: Vec::new(),
+        };
+        leaves.push_branch_children(vec![]);
+        leaves
+    }
+
+    // Returns all of the keys in the trie in sorted order
+    pub fn keys(&self) -> impl Iterator<Item = [u8; 32]> + '_ {
+        self.iter().map(|(key, _)| key)
+    }
+
+    // Returns all of the values in the trie, sorted by their keys
+    pub fn values(&self) -> impl Iterator<Item = [u8; 32]> + '_ {
+        self.iter().map(|(_, value)| value)
+    }
+
+    // Hashes the sorted key-value pairs with sha256. Two tries with the same contents have the same
+    // structural hash, regardless of the committer, and it does not need any commitments
+    // to be recomputed, which makes it a cheap way to compare tries
+    pub fn structural_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for (key, value) in self.iter() {
+            hasher.update(key);
+            hasher.update(value);
+        }
+        hasher.finalize().into()
+    }
+}
+
+// Walks the trie depth first, fetching the children of each node only when it is visited.
+// Since the children of a node are visited in order of their index, the leaves are sorted by key
+pub struct Leaves<'a, Storage> {
+    storage: &'a Storage,
+    // The children which have not been visited yet, the next child to visit is at the end
+    pending: Vec<(Vec<u8>, BranchChild)>,
+    // The leaves of the last stem that was visited, the next leaf is at the end
+    stem_leaves: Vec<([u8; 32], [u8; 32])>,
+}
+
+impl<'a, Storage: ReadOnlyHigherDb> Leaves<'a, Storage> {
+    fn push_branch_children(&mut self, branch_id: Vec<u8>) {
+        // Not all databases return the children in order, so we sort them
+        let mut children = self.storage.get_branch_children(&branch_id);
+        children.sort_by_key(|(index, _)| std::cmp::Reverse(*index));
+
+        for (index, child) in children {
+            let mut child_path = branch_id.clone();
+            child_path.push(index);
+            self.pending.push((child_path, child));
+        }
+    }
+
+    fn push_stem_leaves(&mut self, stem: [u8; 31]) {
+        let mut children = self.storage.get_stem_children(stem);
+        children.sort_by_key(|(suffix, _)| std::cmp::Reverse(*suffix));
+
+        for (suffix, value) in children {
+            let mut key = [0u8; 32];
+            key[0..31].copy_from_slice(&stem);
+            key[31] = suffix;
+            self.stem_leaves.push((key, value));
+        }
+    }
+}
+
+impl<'a, Storage: ReadOnlyHigherDb> Iterator for Leaves<'a, Storage> {
+    type Item = ([u8; 32], [u8; 32]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(leaf) = self.stem_leaves.pop() {
+                return Some(leaf);
+            }
+
+            let (path, child) = self.pending.pop()?;
+            match child {
+                BranchChild::Branch(_) => self.push_branch_children(path),
+                BranchChild::Stem(stem) => self.push_stem_leaves(stem),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::memory_db::MemoryDb;
+    use crate::{trie::Trie, BasicCommitter};
+
+    #[test]
+    fn keys_and_values_are_sorted_by_key() {
+        let mut trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        assert_eq!(trie.keys().count(), 0);
+
+        // These keys create stems at different depths and leaves in both halves of a stem
+        let mut keys = vec![[5u8; 32], [0u8; 32], [255u8; 32]];
+        let mut key = [0u8; 32];
+        key[30] = 1;
+        keys.push(key);
+        key[31] = 200;
+        keys.push(key);
+        key[31] = 3;
+        keys.push(key);
+
+        for key in &keys {
+            trie.insert(*key, [key[0] ^ key[31]; 32]);
+        }
+        // Inserting a key again does not add another key, and the last three keys share a stem
+        trie.insert(keys[0], [1u8; 32]);
+        trie.insert(keys[0], [keys[0][0] ^ keys[0][31]; 32]);
+        let num_distinct_keys = keys.iter().collect::<std::collections::BTreeSet<_>>().len();
+        assert_eq!(num_distinct_keys, 6);
+        assert_eq!(trie.keys().count(), num_distinct_keys);
+        keys.sort();
+
+        let got_keys: Vec<_> = trie.keys().collect();
+        assert_eq!(got_keys, keys);
+
+        let expected_values: Vec<_> = keys.iter().map(|key| [key[0] ^ key[31]; 32]).collect();
+        let got_values: Vec<_> = trie.values().collect();
+        assert_eq!(got_values, expected_values);
+    }
+
+    #[test]
+    fn structural_hash_depends_only_on_contents() {
+        let items: Vec<_> = (0..5u8).map(|i| ([i; 32], [i + 1; 32])).collect();
+
+        let mut trie_a = Trie::new(MemoryDb::new(), BasicCommitter);
+        for (key, value) in &items {
+            trie_a.insert(*key, *value);
+        }
+        // The same contents, inserted in a different order and with an intermediate value
+        let mut trie_b = Trie::new(MemoryDb::new(), BasicCommitter);
+        trie_b.insert(items[2].0, [9u8; 32]);
+        for (key, value) in items.iter().rev() {
+            trie_b.insert(*key, *value);
+        }
+        assert_eq!(trie_a.structural_hash(), trie_b.structural_hash());
+
+        trie_b.insert(items[0].0, [9u8; 32]);
+        assert_ne!(trie_a.structural_hash(), trie_b.structural_hash());
+
+        let empty_trie = Trie::new(MemoryDb::new(), BasicCommitter);
+        assert_ne!(empty_trie.structural_hash(), trie_a.structural_hash());
+    }
+}
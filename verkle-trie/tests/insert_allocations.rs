@@ -0,0 +1,62 @@
+// Counts the heap allocations made while inserting into the trie.
+// This is its own test binary, so that the counting allocator does not see allocations
+// from any other test running at the same time
+use sha2::{Digest, Sha256};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use verkle_trie::database::memory_db::MemoryDb;
+use verkle_trie::trie::Trie;
+use verkle_trie::BasicCommitter;
+
+struct CountingAllocator;
+
+static NUM_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        NUM_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn generate_keys(n: u32) -> Vec<[u8; 32]> {
+    (0..n)
+        .map(|i| {
+            let mut hasher = Sha256::new();
+            hasher.update(i.to_be_bytes());
+            hasher.finalize().try_into().unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn bulk_insert_allocations() {
+    let keys = generate_keys(1_000);
+
+    let mut trie = Trie::new(MemoryDb::new(), BasicCommitter::new());
+    let before = NUM_ALLOCATIONS.load(Ordering::Relaxed);
+    for key in &keys {
+        trie.insert(*key, *key);
+    }
+    let num_allocations = NUM_ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    // The bound counts every allocation made by the trie and by `MemoryDb`, so it depends on both.
+    // In the trie, these are the instruction list, the buffers for the old and new leaf values
+    // and the key paths. In `MemoryDb`, these are the `Vec<u8>` key of each write to the branch table,
+    // and the growth of its hash maps, which is amortised over the inserts.
+    // When branch paths were stored in a `Vec`, this was just over 32 allocations per insert
+    assert!(
+        num_allocations < 31 * keys.len(),
+        "{} allocations for {} inserts",
+        num_allocations,
+        keys.len()
+    );
+}